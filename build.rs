@@ -11,10 +11,10 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 fn git_info() -> Result<String, Box<dyn Error>> {
     let hash = Command::new("git")
-        .args(&["rev-parse", "--short", "HEAD"])
+        .args(["rev-parse", "--short", "HEAD"])
         .output()?;
     let date = Command::new("git")
-        .args(&["log", "-1", "--format=%cs"])
+        .args(["log", "-1", "--format=%cs"])
         .output()?;
 
     Ok(if hash.status.success() && date.status.success() {
@@ -0,0 +1,126 @@
+//! Architecture specific knowledge about the target program
+
+use anyhow::bail;
+use gimli::Register;
+use object::{
+    elf::{self, FileHeader32},
+    read::elf::FileHeader as _,
+    Bytes, Endianness,
+};
+use probe_rs::CoreRegisterAddress;
+
+use crate::{cortexm, riscv, VectorTable};
+
+#[derive(Debug)]
+pub(crate) enum Arch {
+    CortexM(VectorTable),
+    Riscv(riscv::Info),
+}
+
+impl Arch {
+    /// Returns the architecture the ELF file was compiled for
+    pub(crate) fn kind(elf: &[u8]) -> anyhow::Result<Kind> {
+        // NOTE `object::Architecture` has no RISC-V variant (yet) so read the header ourselves
+        let header = FileHeader32::<Endianness>::parse(Bytes(elf))?;
+        Ok(match header.e_machine(header.endian()?) {
+            elf::EM_ARM => Kind::CortexM,
+            elf::EM_RISCV => Kind::Riscv,
+            machine => bail!("unsupported architecture (ELF machine {})", machine),
+        })
+    }
+
+    /// Initial value of the stack pointer
+    pub(crate) fn initial_sp(&self) -> u32 {
+        match self {
+            Arch::CortexM(vector_table) => vector_table.initial_sp,
+            Arch::Riscv(info) => info.stack_start,
+        }
+    }
+
+    /// Address of the handler the core jumps to on a fault, if known
+    pub(crate) fn fault_handler(&self) -> Option<u32> {
+        match self {
+            Arch::CortexM(vector_table) => Some(cortexm::clear_thumb_bit(vector_table.hard_fault)),
+            Arch::Riscv(info) => info.exception_handler,
+        }
+    }
+
+    /// Checks if PC is the fault handler
+    pub(crate) fn is_fault_handler(&self, pc: u32) -> bool {
+        match self {
+            Arch::CortexM(vector_table) => cortexm::is_hard_fault(pc, vector_table),
+            Arch::Riscv(info) => info.exception_handler == Some(pc),
+        }
+    }
+
+    /// Size of an address in bytes
+    pub(crate) fn address_size(&self) -> u8 {
+        match self {
+            Arch::CortexM(_) => cortexm::ADDRESS_SIZE,
+            Arch::Riscv(_) => riscv::ADDRESS_SIZE,
+        }
+    }
+
+    /// Checks if two addresses point to the same subroutine
+    pub(crate) fn subroutine_eq(&self, addr1: u32, addr2: u32) -> bool {
+        match self {
+            Arch::CortexM(_) => cortexm::subroutine_eq(addr1, addr2),
+            Arch::Riscv(_) => addr1 == addr2,
+        }
+    }
+
+    /// DWARF number of the register that holds the return address
+    pub(crate) fn return_address(&self) -> Register {
+        match self {
+            Arch::CortexM(_) => cortexm::LR,
+            Arch::Riscv(_) => riscv::RA,
+        }
+    }
+
+    /// DWARF number of the stack pointer register
+    pub(crate) fn stack_pointer(&self) -> Register {
+        match self {
+            Arch::CortexM(_) => cortexm::SP,
+            Arch::Riscv(_) => riscv::SP,
+        }
+    }
+
+    /// Return address that marks the end of the call stack
+    pub(crate) fn return_address_end(&self) -> u32 {
+        match self {
+            Arch::CortexM(_) => cortexm::LR_END,
+            Arch::Riscv(_) => riscv::RA_END,
+        }
+    }
+
+    /// Checks if `reg` can be read through `dwarf2probe`
+    pub(crate) fn is_core_register(&self, reg: Register) -> bool {
+        match self {
+            Arch::CortexM(_) => reg.0 <= cortexm::PC.0,
+            Arch::Riscv(_) => riscv::is_gpr(reg),
+        }
+    }
+
+    /// Checks if functions must preserve the value of `reg` for their callers
+    pub(crate) fn is_callee_saved(&self, reg: Register) -> bool {
+        match self {
+            Arch::CortexM(_) => cortexm::is_callee_saved(reg),
+            Arch::Riscv(_) => riscv::is_callee_saved(reg),
+        }
+    }
+
+    /// Maps a DWARF register number to the `probe-rs` register address
+    pub(crate) fn dwarf2probe(&self, reg: Register) -> CoreRegisterAddress {
+        match self {
+            // DWARF and `probe-rs` use the same numbering for the core registers
+            Arch::CortexM(_) => CoreRegisterAddress(reg.0),
+            Arch::Riscv(_) => riscv::dwarf2probe(reg),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Kind {
+    CortexM,
+    Riscv,
+}
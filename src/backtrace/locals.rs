@@ -0,0 +1,807 @@
+//! Reads function arguments and local variables of backtrace frames from the DWARF debug info
+
+use std::convert::TryInto as _;
+
+use anyhow::{anyhow, bail};
+use gimli::{
+    constants, AttributeValue, DebuggingInformationEntry, Dwarf, EndianSlice, EvaluationResult,
+    Expression, LittleEndian, Location, Piece, Register, SectionId, Unit, UnitOffset, Value,
+};
+use object::{read::File as ElfFile, Object as _, ObjectSection as _};
+use probe_rs::{Core, MemoryInterface};
+
+use crate::{arch::Arch, registers::FrameRegisters};
+
+type R<'elf> = EndianSlice<'elf, LittleEndian>;
+
+/// Structs and enums nested deeper than this are printed as `{..}`
+const MAX_DEPTH: usize = 3;
+/// Values larger than this (in bytes) are not read from the target
+const MAX_SIZE: u64 = 1024;
+
+/// A function argument or local variable
+#[derive(Debug)]
+pub(crate) struct Variable {
+    pub(crate) name: String,
+    pub(crate) is_argument: bool,
+    pub(crate) type_name: Option<String>,
+    pub(crate) value: VariableValue,
+}
+
+#[derive(Debug)]
+pub(crate) enum VariableValue {
+    Known(String),
+    OptimizedOut,
+    /// The value could not be recovered, e.g. because it lives in a register that was clobbered
+    /// by a callee
+    Unavailable,
+}
+
+pub(crate) struct Locals<'elf, 'c, 'probe> {
+    dwarf: Dwarf<R<'elf>>,
+    units: Vec<Unit<R<'elf>>>,
+    core: &'c mut Core<'probe>,
+    arch: &'c Arch,
+}
+
+/// The variables of a function, or of a function inlined into it
+#[derive(Default)]
+struct Scope {
+    variables: Vec<UnitOffset>,
+}
+
+impl<'elf, 'c, 'probe> Locals<'elf, 'c, 'probe> {
+    pub(crate) fn new(
+        elf: &'elf ElfFile,
+        core: &'c mut Core<'probe>,
+        arch: &'c Arch,
+    ) -> anyhow::Result<Self> {
+        let dwarf = Dwarf::load(
+            |id: SectionId| -> anyhow::Result<_> {
+                let data = match elf.section_by_name(id.name()) {
+                    Some(section) => section.data()?,
+                    None => &[],
+                };
+                Ok(EndianSlice::new(data, LittleEndian))
+            },
+            |_| Ok(EndianSlice::new(&[], LittleEndian)),
+        )?;
+
+        let mut units = vec![];
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            units.push(dwarf.unit(header)?);
+        }
+
+        Ok(Self {
+            dwarf,
+            units,
+            core,
+            arch,
+        })
+    }
+
+    /// Returns the variables in scope at `pc`, one list per (inlined) function, innermost
+    /// function first -- the same order `addr2line` reports inlined frames in
+    pub(crate) fn scopes(&mut self, pc: u32, registers: &FrameRegisters) -> Vec<Vec<Variable>> {
+        match self.try_scopes(pc, registers) {
+            Ok(scopes) => scopes,
+            Err(e) => {
+                log::debug!("failed to read local variables at {:#010x}: {:#}", pc, e);
+                vec![]
+            }
+        }
+    }
+
+    fn try_scopes(
+        &mut self,
+        pc: u32,
+        registers: &FrameRegisters,
+    ) -> anyhow::Result<Vec<Vec<Variable>>> {
+        let pc = u64::from(pc);
+        let mut unit_index = None;
+        for (index, unit) in self.units.iter().enumerate() {
+            if contains(self.dwarf.unit_ranges(unit)?, pc)? {
+                unit_index = Some(index);
+                break;
+            }
+        }
+        let unit_index = match unit_index {
+            Some(index) => index,
+            None => return Ok(vec![]),
+        };
+
+        let mut frame = Frame {
+            dwarf: &self.dwarf,
+            unit: &self.units[unit_index],
+            core: &mut *self.core,
+            arch: self.arch,
+            registers,
+            pc,
+            frame_base: None,
+        };
+
+        let root = frame.unit.entries_tree(None)?.root()?.entry().offset();
+        let (subprogram, mut scopes) = match frame.find_subprogram(root)? {
+            Some(found) => found,
+            None => return Ok(vec![]),
+        };
+
+        frame.frame_base = frame.frame_base(subprogram).unwrap_or_else(|e| {
+            log::debug!("failed to compute the frame base: {:#}", e);
+            None
+        });
+
+        scopes.reverse();
+        Ok(scopes
+            .into_iter()
+            .map(|scope| {
+                scope
+                    .variables
+                    .into_iter()
+                    .filter_map(|offset| frame.variable(offset).transpose())
+                    .filter_map(|variable| {
+                        variable
+                            .map_err(|e| log::debug!("failed to read variable: {:#}", e))
+                            .ok()
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+/// The state of a single stack frame
+struct Frame<'a, 'elf, 'probe> {
+    dwarf: &'a Dwarf<R<'elf>>,
+    unit: &'a Unit<R<'elf>>,
+    core: &'a mut Core<'probe>,
+    arch: &'a Arch,
+    registers: &'a FrameRegisters,
+    pc: u64,
+    frame_base: Option<u64>,
+}
+
+type Die<'u, 'elf> = DebuggingInformationEntry<'u, 'u, R<'elf>>;
+
+impl<'a, 'elf, 'probe> Frame<'a, 'elf, 'probe> {
+    /// Searches the children of `parent` for the subprogram that contains the PC and collects the
+    /// scopes within it, outermost first
+    fn find_subprogram(
+        &self,
+        parent: UnitOffset,
+    ) -> anyhow::Result<Option<(UnitOffset, Vec<Scope>)>> {
+        for offset in children(self.unit, parent)? {
+            let entry = self.unit.entry(offset)?;
+            match entry.tag() {
+                constants::DW_TAG_subprogram if self.contains_pc(&entry)? => {
+                    let mut scopes = vec![Scope::default()];
+                    self.collect_scopes(offset, 0, &mut scopes)?;
+                    return Ok(Some((offset, scopes)));
+                }
+
+                // functions can be nested in modules and `impl` blocks
+                constants::DW_TAG_namespace
+                | constants::DW_TAG_structure_type
+                | constants::DW_TAG_union_type
+                | constants::DW_TAG_enumeration_type => {
+                    if let Some(found) = self.find_subprogram(offset)? {
+                        return Ok(Some(found));
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn collect_scopes(
+        &self,
+        parent: UnitOffset,
+        scope: usize,
+        scopes: &mut Vec<Scope>,
+    ) -> anyhow::Result<()> {
+        for offset in children(self.unit, parent)? {
+            let entry = self.unit.entry(offset)?;
+            match entry.tag() {
+                constants::DW_TAG_formal_parameter | constants::DW_TAG_variable => {
+                    scopes[scope].variables.push(offset)
+                }
+
+                constants::DW_TAG_lexical_block if self.contains_pc(&entry)? => {
+                    self.collect_scopes(offset, scope, scopes)?
+                }
+
+                constants::DW_TAG_inlined_subroutine if self.contains_pc(&entry)? => {
+                    scopes.push(Scope::default());
+                    let inner = scopes.len() - 1;
+                    self.collect_scopes(offset, inner, scopes)?
+                }
+
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn contains_pc(&self, entry: &Die<'_, 'elf>) -> anyhow::Result<bool> {
+        contains(self.dwarf.die_ranges(self.unit, entry)?, self.pc)
+    }
+
+    fn frame_base(&mut self, subprogram: UnitOffset) -> anyhow::Result<Option<u64>> {
+        let entry = self.unit.entry(subprogram)?;
+        let expr = match self.location(&entry, constants::DW_AT_frame_base)? {
+            Some(expr) => expr,
+            None => return Ok(None),
+        };
+
+        let pieces = self.evaluate(expr)?;
+        Ok(match pieces.first().map(|piece| &piece.location) {
+            Some(Location::Register { register }) => Some(u64::from(self.register(*register)?)),
+            Some(Location::Address { address }) => Some(*address),
+            _ => None,
+        })
+    }
+
+    /// Returns `None` if `offset` is not a named variable
+    fn variable(&mut self, offset: UnitOffset) -> anyhow::Result<Option<Variable>> {
+        let entry = self.unit.entry(offset)?;
+        let name = match self.name(&entry)? {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        let ty = self.type_of(&entry)?;
+        let type_name = match ty {
+            Some(ty) => Some(self.type_name(ty)?),
+            None => None,
+        };
+
+        let value = match self.read(&entry, ty) {
+            Ok(value) => value,
+            Err(e) => {
+                log::debug!("failed to read the value of `{}`: {:#}", name, e);
+                VariableValue::Unavailable
+            }
+        };
+
+        Ok(Some(Variable {
+            name,
+            is_argument: entry.tag() == constants::DW_TAG_formal_parameter,
+            type_name,
+            value,
+        }))
+    }
+
+    fn read(
+        &mut self,
+        entry: &Die<'_, 'elf>,
+        ty: Option<UnitOffset>,
+    ) -> anyhow::Result<VariableValue> {
+        let ty = ty.ok_or_else(|| anyhow!("variable has no type"))?;
+        let size = self.byte_size(ty)?;
+
+        let bytes = if let Some(value) = self.attr(entry, constants::DW_AT_const_value)? {
+            match value {
+                AttributeValue::Block(block) => block.to_vec(),
+                AttributeValue::Sdata(value) => const_bytes(value as u64, true, size as usize),
+                value => match value.udata_value() {
+                    Some(value) => const_bytes(value, false, size as usize),
+                    None => bail!("unsupported constant value"),
+                },
+            }
+        } else {
+            let expr = match self.location(entry, constants::DW_AT_location)? {
+                Some(expr) => expr,
+                None => return Ok(VariableValue::OptimizedOut),
+            };
+            let pieces = self.evaluate(expr)?;
+            match self.read_pieces(&pieces, size)? {
+                Some(bytes) => bytes,
+                None => return Ok(VariableValue::OptimizedOut),
+            }
+        };
+
+        Ok(VariableValue::Known(self.format(ty, &bytes, 0)?))
+    }
+
+    /// Returns the location expression that applies at the PC, if any
+    fn location(
+        &self,
+        entry: &Die<'_, 'elf>,
+        name: constants::DwAt,
+    ) -> anyhow::Result<Option<Expression<R<'elf>>>> {
+        Ok(match entry.attr_value(name)? {
+            Some(AttributeValue::Exprloc(expr)) => Some(expr),
+            Some(AttributeValue::LocationListsRef(offset)) => {
+                let mut locations = self.dwarf.locations(self.unit, offset)?;
+                let mut expr = None;
+                while let Some(location) = locations.next()? {
+                    if location.range.begin <= self.pc && self.pc < location.range.end {
+                        expr = Some(location.data);
+                        break;
+                    }
+                }
+                expr
+            }
+            _ => None,
+        })
+    }
+
+    fn evaluate(&mut self, expr: Expression<R<'elf>>) -> anyhow::Result<Vec<Piece<R<'elf>>>> {
+        let mut evaluation = expr.evaluation(self.unit.encoding());
+        let mut result = evaluation.evaluate()?;
+        loop {
+            result = match result {
+                EvaluationResult::Complete => break,
+                EvaluationResult::RequiresMemory { address, size, .. } => {
+                    let mut buffer = [0; 8];
+                    self.core
+                        .read_8(address as u32, &mut buffer[..usize::from(size)])?;
+                    evaluation.resume_with_memory(Value::Generic(u64::from_le_bytes(buffer)))?
+                }
+                EvaluationResult::RequiresRegister { register, .. } => {
+                    let value = self.register(register)?;
+                    evaluation.resume_with_register(Value::Generic(u64::from(value)))?
+                }
+                EvaluationResult::RequiresFrameBase => {
+                    let frame_base = self
+                        .frame_base
+                        .ok_or_else(|| anyhow!("frame base is not known"))?;
+                    evaluation.resume_with_frame_base(frame_base)?
+                }
+                EvaluationResult::RequiresCallFrameCfa => {
+                    let cfa = self
+                        .registers
+                        .cfa
+                        .ok_or_else(|| anyhow!("CFA is not known"))?;
+                    evaluation.resume_with_call_frame_cfa(u64::from(cfa))?
+                }
+                EvaluationResult::RequiresRelocatedAddress(address) => {
+                    evaluation.resume_with_relocated_address(address)?
+                }
+                result => bail!("unsupported DWARF expression ({:?})", result),
+            };
+        }
+
+        Ok(evaluation.result())
+    }
+
+    fn register(&mut self, reg: Register) -> anyhow::Result<u32> {
+        if let Some(value) = self.registers.values.get(&reg.0) {
+            return Ok(*value);
+        }
+
+        // callees may have overwritten the register unless they had to preserve it
+        let is_intact = self.registers.is_top || self.arch.is_callee_saved(reg);
+        if !is_intact || !self.arch.is_core_register(reg) {
+            bail!("register {} is not available in this frame", reg.0);
+        }
+
+        Ok(self.core.read_core_reg(self.arch.dwarf2probe(reg))?)
+    }
+
+    /// Returns `None` if the value has been optimized out
+    fn read_pieces(
+        &mut self,
+        pieces: &[Piece<R<'elf>>],
+        size: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        if size > MAX_SIZE {
+            bail!("value is too large ({} bytes)", size);
+        }
+
+        let mut bytes = vec![];
+        for piece in pieces {
+            let piece_size = match piece.size_in_bits {
+                Some(bits) if bits % 8 == 0 && piece.bit_offset.is_none() => bits / 8,
+                Some(_) => bail!("bit pieces are not supported"),
+                None => size,
+            };
+
+            let mut piece_bytes = match piece.location {
+                Location::Empty => return Ok(None),
+                Location::Address { address } => {
+                    let mut buffer = vec![0; piece_size as usize];
+                    self.core.read_8(address as u32, &mut buffer)?;
+                    buffer
+                }
+                Location::Register { register } => self.register(register)?.to_le_bytes().to_vec(),
+                Location::Value { value } => value.to_u64(!0)?.to_le_bytes().to_vec(),
+                Location::Bytes { value } => value.to_vec(),
+                Location::ImplicitPointer { .. } => bail!("implicit pointers are not supported"),
+            };
+            piece_bytes.resize(piece_size as usize, 0);
+            bytes.extend(piece_bytes);
+        }
+
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        bytes.resize(size as usize, 0);
+        Ok(Some(bytes))
+    }
+
+    /// Formats the value of type `ty` stored in `bytes`
+    fn format(&self, ty: UnitOffset, bytes: &[u8], depth: usize) -> anyhow::Result<String> {
+        let entry = self.unit.entry(ty)?;
+        let size = (self.byte_size(ty)? as usize).min(bytes.len());
+        let bytes = &bytes[..size];
+
+        Ok(match entry.tag() {
+            constants::DW_TAG_base_type => {
+                let encoding = match entry.attr_value(constants::DW_AT_encoding)? {
+                    Some(AttributeValue::Encoding(encoding)) => encoding,
+                    _ => bail!("base type without encoding"),
+                };
+                format_base(encoding, bytes).unwrap_or_else(|| "?".to_string())
+            }
+
+            constants::DW_TAG_pointer_type
+            | constants::DW_TAG_reference_type
+            | constants::DW_TAG_rvalue_reference_type => format!("{:#010x}", le_u64(bytes)),
+
+            constants::DW_TAG_enumeration_type => {
+                let value = le_u64(bytes);
+                let mut name = None;
+                for offset in children(self.unit, ty)? {
+                    let enumerator = self.unit.entry(offset)?;
+                    let discriminant = enumerator
+                        .attr_value(constants::DW_AT_const_value)?
+                        .and_then(|value| value.udata_value());
+                    if discriminant == Some(value) {
+                        name = self.name(&enumerator)?;
+                        break;
+                    }
+                }
+                name.unwrap_or_else(|| value.to_string())
+            }
+
+            constants::DW_TAG_structure_type | constants::DW_TAG_union_type => {
+                if depth >= MAX_DEPTH {
+                    return Ok("{..}".to_string());
+                }
+                self.format_struct(ty, bytes, depth)?
+            }
+
+            constants::DW_TAG_typedef
+            | constants::DW_TAG_const_type
+            | constants::DW_TAG_volatile_type => match self.type_of(&entry)? {
+                Some(inner) => self.format(inner, bytes, depth)?,
+                None => "?".to_string(),
+            },
+
+            _ => "..".to_string(),
+        })
+    }
+
+    fn format_struct(&self, ty: UnitOffset, bytes: &[u8], depth: usize) -> anyhow::Result<String> {
+        let name = self.name(&self.unit.entry(ty)?)?.unwrap_or_default();
+
+        let mut fields = vec![];
+        for offset in children(self.unit, ty)? {
+            let child = self.unit.entry(offset)?;
+            match child.tag() {
+                constants::DW_TAG_member => {
+                    let field_name = self.name(&child)?.unwrap_or_default();
+                    let value = self.format_member(&child, bytes, depth)?;
+                    fields.push((field_name, value));
+                }
+
+                // Rust enum
+                constants::DW_TAG_variant_part => return self.format_variant(offset, bytes, depth),
+
+                _ => {}
+            }
+        }
+
+        Ok(format_fields(&name, &fields))
+    }
+
+    fn format_member(
+        &self,
+        member: &Die<'_, 'elf>,
+        bytes: &[u8],
+        depth: usize,
+    ) -> anyhow::Result<String> {
+        let offset = member_offset(member)? as usize;
+        let ty = self
+            .type_of(member)?
+            .ok_or_else(|| anyhow!("member has no type"))?;
+        let bytes = bytes
+            .get(offset..)
+            .ok_or_else(|| anyhow!("member is out of bounds"))?;
+        self.format(ty, bytes, depth + 1)
+    }
+
+    fn format_variant(
+        &self,
+        variant_part: UnitOffset,
+        bytes: &[u8],
+        depth: usize,
+    ) -> anyhow::Result<String> {
+        let entry = self.unit.entry(variant_part)?;
+        let discriminant = match entry.attr_value(constants::DW_AT_discr)? {
+            Some(AttributeValue::UnitRef(discr)) => {
+                let discr = self.unit.entry(discr)?;
+                let offset = member_offset(&discr)? as usize;
+                let size = match self.type_of(&discr)? {
+                    Some(ty) => self.byte_size(ty)? as usize,
+                    None => bail!("discriminant has no type"),
+                };
+                Some(le_u64(
+                    bytes
+                        .get(offset..offset + size)
+                        .ok_or_else(|| anyhow!("discriminant is out of bounds"))?,
+                ))
+            }
+            _ => None,
+        };
+
+        let mut selected = None;
+        for offset in children(self.unit, variant_part)? {
+            let variant = self.unit.entry(offset)?;
+            if variant.tag() != constants::DW_TAG_variant {
+                continue;
+            }
+
+            match variant
+                .attr_value(constants::DW_AT_discr_value)?
+                .and_then(|value| value.udata_value())
+            {
+                Some(value) if Some(value) == discriminant => {
+                    selected = Some(offset);
+                    break;
+                }
+                Some(_) => {}
+                // the default variant
+                None => selected = selected.or(Some(offset)),
+            }
+        }
+
+        let variant = selected.ok_or_else(|| anyhow!("no variant matches the discriminant"))?;
+        for offset in children(self.unit, variant)? {
+            let member = self.unit.entry(offset)?;
+            if member.tag() == constants::DW_TAG_member {
+                // the member's type is a struct named after the variant; don't count it as
+                // nesting level
+                return self.format_member(&member, bytes, depth.saturating_sub(1));
+            }
+        }
+
+        bail!("variant has no member")
+    }
+
+    fn type_name(&self, ty: UnitOffset) -> anyhow::Result<String> {
+        let entry = self.unit.entry(ty)?;
+        if let Some(name) = self.name(&entry)? {
+            return Ok(name);
+        }
+
+        Ok(match entry.tag() {
+            constants::DW_TAG_pointer_type => match self.type_of(&entry)? {
+                Some(pointee) => format!("*{}", self.type_name(pointee)?),
+                None => "*void".to_string(),
+            },
+            constants::DW_TAG_const_type | constants::DW_TAG_volatile_type => {
+                match self.type_of(&entry)? {
+                    Some(inner) => self.type_name(inner)?,
+                    None => "?".to_string(),
+                }
+            }
+            _ => "?".to_string(),
+        })
+    }
+
+    fn byte_size(&self, ty: UnitOffset) -> anyhow::Result<u64> {
+        let entry = self.unit.entry(ty)?;
+        if let Some(size) = entry
+            .attr_value(constants::DW_AT_byte_size)?
+            .and_then(|size| size.udata_value())
+        {
+            return Ok(size);
+        }
+
+        match entry.tag() {
+            constants::DW_TAG_pointer_type
+            | constants::DW_TAG_reference_type
+            | constants::DW_TAG_rvalue_reference_type => {
+                Ok(u64::from(self.unit.encoding().address_size))
+            }
+            _ => match self.type_of(&entry)? {
+                Some(inner) => self.byte_size(inner),
+                None => bail!("type has no size"),
+            },
+        }
+    }
+
+    /// Looks up attribute `name`, following `DW_AT_abstract_origin` if necessary
+    fn attr(
+        &self,
+        entry: &Die<'_, 'elf>,
+        name: constants::DwAt,
+    ) -> anyhow::Result<Option<AttributeValue<R<'elf>>>> {
+        if let Some(value) = entry.attr_value(name)? {
+            return Ok(Some(value));
+        }
+
+        if let Some(AttributeValue::UnitRef(origin)) =
+            entry.attr_value(constants::DW_AT_abstract_origin)?
+        {
+            return self.attr(&self.unit.entry(origin)?, name);
+        }
+
+        Ok(None)
+    }
+
+    fn name(&self, entry: &Die<'_, 'elf>) -> anyhow::Result<Option<String>> {
+        Ok(match self.attr(entry, constants::DW_AT_name)? {
+            Some(name) => Some(
+                self.dwarf
+                    .attr_string(self.unit, name)?
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
+            None => None,
+        })
+    }
+
+    fn type_of(&self, entry: &Die<'_, 'elf>) -> anyhow::Result<Option<UnitOffset>> {
+        Ok(match self.attr(entry, constants::DW_AT_type)? {
+            Some(AttributeValue::UnitRef(ty)) => Some(ty),
+            _ => None,
+        })
+    }
+}
+
+/// Returns the offsets of `parent`'s children
+fn children(unit: &Unit<R>, parent: UnitOffset) -> anyhow::Result<Vec<UnitOffset>> {
+    let mut tree = unit.entries_tree(Some(parent))?;
+    let root = tree.root()?;
+    let mut children = root.children();
+    let mut offsets = vec![];
+    while let Some(child) = children.next()? {
+        offsets.push(child.entry().offset());
+    }
+    Ok(offsets)
+}
+
+fn contains(mut ranges: gimli::RangeIter<R>, address: u64) -> anyhow::Result<bool> {
+    while let Some(range) = ranges.next()? {
+        if range.begin <= address && address < range.end {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn member_offset(member: &Die) -> anyhow::Result<u64> {
+    match member.attr_value(constants::DW_AT_data_member_location)? {
+        Some(offset) => offset
+            .udata_value()
+            .ok_or_else(|| anyhow!("unsupported member location")),
+        // union members
+        None => Ok(0),
+    }
+}
+
+fn format_fields(name: &str, fields: &[(String, String)]) -> String {
+    if fields.is_empty() {
+        return name.to_string();
+    }
+
+    // tuples and tuple structs
+    if fields.iter().all(|(name, _)| name.starts_with("__")) {
+        let values = fields.iter().map(|(_, value)| &**value).collect::<Vec<_>>();
+        // tuples are named after their type, e.g. `(u8, i32)`; don't repeat that
+        let name = if name.starts_with('(') { "" } else { name };
+        return format!("{}({})", name, values.join(", "));
+    }
+
+    let fields = fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<_>>();
+    format!("{} {{ {} }}", name, fields.join(", "))
+}
+
+/// Formats a value of a base type (integer, float, `bool`, `char`)
+fn format_base(encoding: constants::DwAte, bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        return None;
+    }
+
+    let unsigned = le_u64(bytes);
+    Some(match encoding {
+        constants::DW_ATE_boolean => (unsigned != 0).to_string(),
+        constants::DW_ATE_unsigned => unsigned.to_string(),
+        constants::DW_ATE_signed => {
+            let shift = 64 - 8 * bytes.len().min(8) as u32;
+            (((unsigned << shift) as i64) >> shift).to_string()
+        }
+        constants::DW_ATE_UTF | constants::DW_ATE_unsigned_char | constants::DW_ATE_signed_char => {
+            format!("{:?}", std::char::from_u32(unsigned as u32)?)
+        }
+        constants::DW_ATE_float => match bytes.len() {
+            4 => f32::from_le_bytes(bytes.try_into().ok()?).to_string(),
+            8 => f64::from_le_bytes(bytes.try_into().ok()?).to_string(),
+            _ => return None,
+        },
+        _ => return None,
+    })
+}
+
+/// Encodes an integer `DW_AT_const_value` as a value of `size` bytes, which may be smaller or
+/// larger than the 8 bytes DWARF stores it in
+fn const_bytes(value: u64, signed: bool, size: usize) -> Vec<u8> {
+    let fill = if signed && (value as i64) < 0 {
+        0xff
+    } else {
+        0
+    };
+    let mut bytes = value.to_le_bytes().to_vec();
+    bytes.resize(size, fill);
+    bytes
+}
+
+/// Reads a little endian integer of up to 8 bytes
+fn le_u64(bytes: &[u8]) -> u64 {
+    let mut buffer = [0; 8];
+    let len = bytes.len().min(buffer.len());
+    buffer[..len].copy_from_slice(&bytes[..len]);
+    u64::from_le_bytes(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(constants::DW_ATE_boolean, &[1], Some("true"))]
+    #[case(constants::DW_ATE_unsigned, &[0x2a, 0, 0, 0], Some("42"))]
+    #[case(constants::DW_ATE_signed, &[0xff, 0xff], Some("-1"))]
+    #[case(constants::DW_ATE_signed, &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], Some("-2"))]
+    #[case(constants::DW_ATE_UTF, &[0x41, 0, 0, 0], Some("'A'"))]
+    #[case(constants::DW_ATE_float, &[0, 0, 0xc0, 0x3f], Some("1.5"))]
+    #[case(constants::DW_ATE_float, &[0, 0, 0xc0], None)]
+    #[case(constants::DW_ATE_signed, &[], None)]
+    #[case(constants::DW_ATE_unsigned, &[], None)]
+    fn base_types(
+        #[case] encoding: constants::DwAte,
+        #[case] bytes: &[u8],
+        #[case] expected: Option<&str>,
+    ) {
+        assert_eq!(expected.map(str::to_string), format_base(encoding, bytes));
+    }
+
+    #[rstest]
+    #[case(0x1234, false, 2, &[0x34, 0x12])]
+    #[case(5, false, 16, &[5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])]
+    #[case(-2i64 as u64, true, 12, &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff])]
+    #[case(7, false, 0, &[])]
+    fn const_values(
+        #[case] value: u64,
+        #[case] signed: bool,
+        #[case] size: usize,
+        #[case] expected: &[u8],
+    ) {
+        assert_eq!(expected, &const_bytes(value, signed, size)[..]);
+    }
+
+    #[rstest]
+    #[case("Foo", &[], "Foo")]
+    #[case("Some", &[("__0", "5")], "Some(5)")]
+    #[case("(u8, i32)", &[("__0", "1"), ("__1", "-1")], "(1, -1)")]
+    #[case("Point", &[("x", "1"), ("y", "2")], "Point { x: 1, y: 2 }")]
+    fn struct_fields(#[case] name: &str, #[case] fields: &[(&str, &str)], #[case] expected: &str) {
+        let fields = fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, format_fields(name, &fields));
+    }
+}
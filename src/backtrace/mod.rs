@@ -1,11 +1,20 @@
-use std::{collections::HashSet, path::Path};
+use std::{collections::HashSet, env, io, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Context as _};
 
 use object::read::File as ElfFile;
 use probe_rs::{config::RamRegion, Core};
 
-use crate::{Outcome, VectorTable};
+use crate::{arch::Arch, hyperlink, Outcome};
+
+pub(crate) use self::unwind::UnwindError;
+
+use self::unwind::RawFrame;
 
+mod locals;
 mod pp;
+mod scan;
+mod source;
 mod symbolicate;
 mod unwind;
 
@@ -14,6 +23,46 @@ pub(crate) struct Settings<'p> {
     pub(crate) max_backtrace_len: u32,
     pub(crate) force_backtrace: bool,
     pub(crate) shorten_paths: bool,
+    pub(crate) scan_stack: bool,
+    /// Number of source lines to print above and below each frame's location
+    pub(crate) source_context: u32,
+    pub(crate) show_locals: bool,
+    pub(crate) verbosity: Verbosity,
+    /// Turn frame locations into terminal hyperlinks
+    pub(crate) hyperlinks: Option<&'p hyperlink::Template>,
+}
+
+/// How many frames to print
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Verbosity {
+    /// Hide frames of the runtime crates (`cortex-m-rt`, `panic-probe`, `core`, etc.)
+    Short,
+    Full,
+}
+
+impl Verbosity {
+    /// Reads the verbosity from the `RUST_BACKTRACE` environment variable, like `std` does, except
+    /// that frames are only hidden if it asks for short backtraces
+    pub(crate) fn from_env() -> Self {
+        match env::var("RUST_BACKTRACE").as_deref() {
+            Ok("short") | Ok("1") => Verbosity::Short,
+            _ => Verbosity::Full,
+        }
+    }
+}
+
+impl FromStr for Verbosity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "short" => Ok(Verbosity::Short),
+            "full" => Ok(Verbosity::Full),
+            _ => Err(anyhow!(
+                "invalid backtrace verbosity (expected `short` or `full`)"
+            )),
+        }
+    }
 }
 
 /// (virtually) unwinds the target's program and prints its backtrace
@@ -21,18 +70,45 @@ pub(crate) fn print(
     core: &mut Core,
     debug_frame: &[u8],
     elf: &ElfFile,
-    vector_table: &VectorTable,
+    arch: &Arch,
     sp_ram_region: &Option<RamRegion>,
     live_functions: &HashSet<&str>,
     settings: &Settings,
 ) -> anyhow::Result<Outcome> {
-    let unwind = unwind::target(core, debug_frame, vector_table, sp_ram_region)?;
+    let mut unwind = unwind::target(core, debug_frame, arch, sp_ram_region)
+        .context("failed to read the registers of the halted core")?;
+
+    let mut num_probable_frames = 0;
+    if unwind.error.is_some() && settings.scan_stack {
+        if let Arch::Riscv(_) = arch {
+            log::warn!("stack scanning is not supported on RISC-V targets");
+        } else if let Some(sp) = unwind.sp {
+            match scan::stack(core, sp, arch.initial_sp(), elf, settings.max_backtrace_len) {
+                Ok(pcs) => {
+                    num_probable_frames = pcs.len();
+                    unwind
+                        .raw_frames
+                        .extend(pcs.into_iter().map(|pc| RawFrame::Probable { pc }));
+                }
+                Err(e) => log::warn!("failed to scan the stack: {:#}", e),
+            }
+        }
+    }
+
+    let locals = if settings.show_locals {
+        locals::Locals::new(elf, core, arch)
+            .map_err(|e| log::warn!("failed to load the debug info of local variables: {:#}", e))
+            .ok()
+    } else {
+        None
+    };
 
     let frames = symbolicate::frames(
         &unwind.raw_frames,
         live_functions,
         settings.current_dir,
         elf,
+        locals,
     );
 
     let contains_exception = unwind
@@ -42,14 +118,29 @@ pub(crate) fn print(
 
     let print_backtrace = settings.force_backtrace
         || unwind.outcome == Outcome::StackOverflow
-        || unwind.corrupted
+        || unwind.error.is_some()
         || contains_exception;
 
     if print_backtrace && settings.max_backtrace_len > 0 {
-        pp::backtrace(&frames, &settings);
+        pp::backtrace(
+            &mut io::stdout().lock(),
+            &frames,
+            unwind.error.as_ref(),
+            settings,
+        )?;
 
-        if unwind.corrupted {
-            log::warn!("call stack was corrupted; unwinding could not be completed");
+        if unwind.error.is_some() {
+            if num_probable_frames != 0 {
+                log::warn!(
+                    "{} frames marked as `(probable)` were recovered by scanning the stack; \
+                    they may not be part of the actual call stack",
+                    num_probable_frames
+                );
+            } else if !settings.scan_stack {
+                log::warn!(
+                    "note: re-run with `--scan-stack` to search the stack for probable frames"
+                );
+            }
         }
     }
 
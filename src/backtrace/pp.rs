@@ -1,65 +1,75 @@
 //! Pretty printing the backtrace
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
 
 use colored::Colorize as _;
 
 use crate::dep;
 
-use super::{symbolicate::Frame, Settings};
+use super::{
+    locals::{Variable, VariableValue},
+    source::Sources,
+    symbolicate::{Frame, Subroutine},
+    unwind::UnwindError,
+    Settings, Verbosity,
+};
 
-/// Pretty prints processed backtrace frames up to `max_backtrace_len`
-pub(crate) fn backtrace(frames: &[Frame], settings: &Settings) {
-    println!("{}", "stack backtrace:".dimmed());
+/// Crates whose frames are hidden in short backtraces
+const RUNTIME_CRATES: &[&str] = &[
+    "alloc",
+    "compiler_builtins",
+    "core",
+    "cortex-m",
+    "cortex-m-rt",
+    "defmt",
+    "defmt-rtt",
+    "panic-abort",
+    "panic-halt",
+    "panic-probe",
+    "riscv",
+    "riscv-rt",
+    "std",
+];
 
+/// Pretty prints processed backtrace frames up to `max_backtrace_len`, followed by the reason why
+/// unwinding stopped early (if it did)
+pub(crate) fn backtrace(
+    w: &mut impl Write,
+    frames: &[Frame],
+    error: Option<&UnwindError>,
+    settings: &Settings,
+) -> io::Result<()> {
+    writeln!(w, "{}", "stack backtrace:".dimmed())?;
+
+    let mut sources = Sources::new(settings.current_dir);
     let mut frame_index = 0;
+    // hidden frames don't count against `max_backtrace_len`
+    let mut num_printed = 0;
+    let mut num_hidden = 0;
+    let mut any_hidden = false;
     for frame in frames {
         match frame {
             Frame::Exception => {
-                println!("      <exception entry>");
+                print_hidden(w, &mut num_hidden)?;
+                writeln!(w, "      <exception entry>")?;
             }
 
-            Frame::Subroutine(subroutine) => {
-                let name = match &subroutine.name_or_pc {
-                    either::Either::Left(name) => Cow::Borrowed(name),
-                    either::Either::Right(pc) => Cow::Owned(format!("??? (PC={:#010x})", pc)),
-                };
-
-                let is_local_function = subroutine
-                    .location
-                    .as_ref()
-                    .map(|location| location.path_is_relative)
-                    .unwrap_or(false);
-
-                let line = format!("{:>4}: {}", frame_index, name);
-                let colorized_line = if is_local_function {
-                    line.bold()
+            Frame::Subroutine(subroutine) | Frame::Probable(subroutine) => {
+                if settings.verbosity == Verbosity::Short && is_runtime(subroutine) {
+                    num_hidden += 1;
+                    any_hidden = true;
                 } else {
-                    line.normal()
-                };
-                println!("{}", colorized_line);
-
-                if let Some(location) = &subroutine.location {
-                    let dep_path = dep::Path::from_std_path(&location.path);
-
-                    let path = if settings.shorten_paths {
-                        dep_path.format_short()
-                    } else {
-                        dep_path.format_highlight()
-                    };
-
-                    let line = location.line;
-                    let column = location
-                        .column
-                        .map(|column| Cow::Owned(format!(":{}", column)))
-                        .unwrap_or(Cow::Borrowed(""));
-
-                    println!("        at {}:{}{}", path, line, column);
+                    print_hidden(w, &mut num_hidden)?;
+                    print_subroutine(w, frame, subroutine, frame_index, &mut sources, settings)?;
+                    num_printed += 1;
                 }
 
                 frame_index += 1;
 
-                if frame_index >= settings.max_backtrace_len {
+                if num_printed >= settings.max_backtrace_len {
                     log::warn!(
                         "maximum backtrace length of {} reached; cutting off the rest.const ",
                         settings.max_backtrace_len
@@ -71,4 +81,223 @@ pub(crate) fn backtrace(frames: &[Frame], settings: &Settings) {
             }
         }
     }
+    print_hidden(w, &mut num_hidden)?;
+    if any_hidden {
+        log::info!("note: re-run with `--backtrace=full` to show all frames");
+    }
+    if let Some(error) = error {
+        writeln!(w, "{} {}", "unwinding stopped early:".yellow(), error)?;
+    }
+    Ok(())
+}
+
+fn print_subroutine(
+    w: &mut impl Write,
+    frame: &Frame,
+    subroutine: &Subroutine,
+    frame_index: u32,
+    sources: &mut Sources,
+    settings: &Settings,
+) -> io::Result<()> {
+    let name = match &subroutine.name_or_pc {
+        either::Either::Left(name) => Cow::Borrowed(name),
+        either::Either::Right(pc) => Cow::Owned(format!("??? (PC={:#010x})", pc)),
+    };
+
+    let is_local_function = subroutine
+        .location
+        .as_ref()
+        .map(|location| location.path_is_relative)
+        .unwrap_or(false);
+
+    let line = format!("{:>4}: {}", frame_index, name);
+    let colorized_line = if is_local_function {
+        line.bold()
+    } else {
+        line.normal()
+    };
+    if let Frame::Probable(_) = frame {
+        writeln!(w, "{} {}", colorized_line, "(probable)".yellow())?;
+    } else {
+        writeln!(w, "{}", colorized_line)?;
+    }
+
+    if let Some(location) = &subroutine.location {
+        let dep_path = dep::Path::from_std_path(&location.path);
+
+        let mut path = if settings.shorten_paths {
+            dep_path.format_short()
+        } else {
+            dep_path.format_highlight()
+        };
+
+        if let Some(hyperlinks) = settings.hyperlinks {
+            // link to the file on this machine, even if its path has been shortened
+            let target = sources
+                .resolve(location)
+                .unwrap_or_else(|| location.path.clone());
+            path = hyperlinks.link(&path, &target, location.line, location.column);
+        }
+
+        let line = location.line;
+        let column = location
+            .column
+            .map(|column| Cow::Owned(format!(":{}", column)))
+            .unwrap_or(Cow::Borrowed(""));
+
+        writeln!(w, "        at {}:{}{}", path, line, column)?;
+
+        if settings.source_context != 0 {
+            sources.print_snippet(w, location, settings.source_context)?;
+        }
+    }
+
+    for variable in &subroutine.variables {
+        print_variable(w, variable)?;
+    }
+    Ok(())
+}
+
+/// Prints the "N frames hidden" line, if any frames were hidden since the last call
+fn print_hidden(w: &mut impl Write, num_hidden: &mut u32) -> io::Result<()> {
+    match *num_hidden {
+        0 => return Ok(()),
+        1 => writeln!(w, "{}", "      ... 1 frame hidden".dimmed())?,
+        n => writeln!(w, "{}", format!("      ... {} frames hidden", n).dimmed())?,
+    }
+    *num_hidden = 0;
+    Ok(())
+}
+
+/// Checks if `subroutine` belongs to the runtime rather than to the application
+fn is_runtime(subroutine: &Subroutine) -> bool {
+    match &subroutine.location {
+        Some(location) => dep::Path::from_std_path(&location.path)
+            .crate_name()
+            .map(|crate_name| RUNTIME_CRATES.contains(&crate_name))
+            .unwrap_or(false),
+        // no debug info, e.g. `??? (PC=..)` frames; these may be the interesting ones
+        None => false,
+    }
+}
+
+fn print_variable(w: &mut impl Write, variable: &Variable) -> io::Result<()> {
+    let keyword = if variable.is_argument { "arg" } else { "let" };
+    let type_name = variable
+        .type_name
+        .as_ref()
+        .map(|type_name| Cow::Owned(format!(": {}", type_name)))
+        .unwrap_or(Cow::Borrowed(""));
+    let value = match &variable.value {
+        VariableValue::Known(value) => value.normal(),
+        VariableValue::OptimizedOut => "<optimized out>".dimmed(),
+        VariableValue::Unavailable => "<unavailable>".dimmed(),
+    };
+
+    writeln!(
+        w,
+        "          {} {}{} = {}",
+        keyword.dimmed(),
+        variable.name,
+        type_name,
+        value
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use either::Either;
+
+    use super::*;
+    use crate::{
+        backtrace::symbolicate::Location,
+        backtrace::unwind::{Output, RawFrame},
+        Outcome,
+    };
+
+    fn subroutine(name: &str, path: Option<&str>) -> Frame {
+        Frame::Subroutine(Subroutine {
+            name_or_pc: Either::Left(name.to_string()),
+            location: path.map(|path| Location {
+                column: None,
+                path_is_relative: !path.starts_with('/'),
+                line: 1,
+                path: path.into(),
+            }),
+            variables: vec![],
+        })
+    }
+
+    #[test]
+    fn short_backtrace() {
+        colored::control::set_override(false);
+
+        let frames = [
+            subroutine(
+                "core::panicking::panic",
+                Some(
+                    "/rustc/9bc8c42bb2f19e745a63f3445f1ac248fb015e53/library/core/src/panicking.rs",
+                ),
+            ),
+            subroutine("HardFaultTrampoline", None),
+            subroutine("app::main", Some("src/main.rs")),
+        ];
+        let settings = Settings {
+            max_backtrace_len: 2,
+            verbosity: Verbosity::Short,
+            ..settings()
+        };
+
+        let mut printed = vec![];
+        backtrace(&mut printed, &frames, None, &settings).unwrap();
+        assert_eq!(
+            "stack backtrace:
+      ... 1 frame hidden
+   1: HardFaultTrampoline
+   2: app::main
+        at src/main.rs:1
+",
+            String::from_utf8(printed).unwrap()
+        );
+    }
+
+    #[test]
+    fn partial_backtrace() {
+        colored::control::set_override(false);
+
+        let output = Output {
+            outcome: Outcome::HardFault,
+            raw_frames: vec![RawFrame::Exception],
+            sp: Some(0x2000_0000),
+            error: Some(UnwindError::CorruptedStack),
+        };
+        let frames = [subroutine("app::main", None), Frame::Exception];
+
+        let mut printed = vec![];
+        backtrace(&mut printed, &frames, output.error.as_ref(), &settings()).unwrap();
+        assert_eq!(
+            "stack backtrace:
+   0: app::main
+      <exception entry>
+unwinding stopped early: call stack was corrupted
+",
+            String::from_utf8(printed).unwrap()
+        );
+    }
+
+    fn settings() -> Settings<'static> {
+        Settings {
+            current_dir: Path::new("/"),
+            max_backtrace_len: 50,
+            force_backtrace: false,
+            shorten_paths: false,
+            scan_stack: false,
+            source_context: 0,
+            show_locals: false,
+            verbosity: Verbosity::Full,
+            hyperlinks: None,
+        }
+    }
 }
@@ -0,0 +1,127 @@
+//! Heuristic stack scanning; a last resort for when unwinding could not be completed
+
+use std::convert::TryInto;
+
+use object::{read::File as ElfFile, Object as _, ObjectSection as _};
+use probe_rs::{Core, MemoryInterface};
+
+use crate::cortexm;
+
+/// Maximum number of stack words we are willing to read from the target
+const MAX_WORDS: u32 = 4 * 1024;
+
+/// Scans the stack from `sp` up to `stack_top` for words that look like return addresses
+///
+/// A word is considered a return address if it has its Thumb bit set and points right after a
+/// `BL` or `BLX` instruction in the `.text` section. The result is a list of *probable* program
+/// counters; some of them may be stale values left on the stack by returned functions
+pub(crate) fn stack(
+    core: &mut Core,
+    sp: u32,
+    stack_top: u32,
+    elf: &ElfFile,
+    max_frames: u32,
+) -> anyhow::Result<Vec<u32>> {
+    let text = match Text::from_elf(elf) {
+        Some(text) => text,
+        None => return Ok(vec![]),
+    };
+
+    let start = sp & !0b11;
+    if start >= stack_top {
+        return Ok(vec![]);
+    }
+    let num_words = ((stack_top - start) / 4).min(MAX_WORDS);
+    log::debug!(
+        "scanning {} stack words starting at {:#010X}",
+        num_words,
+        start
+    );
+
+    let mut words = vec![0; num_words as usize];
+    core.read_32(start, &mut words)?;
+
+    Ok(words
+        .into_iter()
+        .filter(|word| cortexm::is_thumb_bit_set(*word) && text.follows_call(*word))
+        .map(cortexm::clear_thumb_bit)
+        .take(max_frames as usize)
+        .collect())
+}
+
+/// Contents of the `.text` section
+struct Text<'a> {
+    start: u32,
+    data: &'a [u8],
+}
+
+impl<'a> Text<'a> {
+    fn from_elf(elf: &'a ElfFile) -> Option<Self> {
+        let section = elf.section_by_name(".text")?;
+
+        Some(Text {
+            start: section.address().try_into().ok()?,
+            data: section.data().ok()?,
+        })
+    }
+
+    fn halfword(&self, addr: u32) -> Option<u16> {
+        let offset = addr.checked_sub(self.start)? as usize;
+        let bytes = self.data.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Checks if `return_address` points right after a `BL` or `BLX` instruction
+    fn follows_call(&self, return_address: u32) -> bool {
+        let addr = cortexm::clear_thumb_bit(return_address);
+
+        // `BL <label>` (T1); 32-bit instruction
+        let is_bl = addr
+            .checked_sub(4)
+            .and_then(|addr| Some((self.halfword(addr)?, self.halfword(addr + 2)?)))
+            .map(|(hw1, hw2)| is_bl(hw1, hw2))
+            .unwrap_or(false);
+
+        // `BLX <Rm>` (T1); 16-bit instruction
+        let is_blx = addr
+            .checked_sub(2)
+            .and_then(|addr| self.halfword(addr))
+            .map(is_blx)
+            .unwrap_or(false);
+
+        is_bl || is_blx
+    }
+}
+
+fn is_bl(hw1: u16, hw2: u16) -> bool {
+    hw1 & 0xF800 == 0xF000 && hw2 & 0xD000 == 0xD000
+}
+
+fn is_blx(hw: u16) -> bool {
+    hw & 0xFF87 == 0x4780
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    // bl 0x...
+    #[case(&[0x00, 0xf0, 0x08, 0xf8], 0x1005, true)]
+    // blx r3
+    #[case(&[0x00, 0xbf, 0x98, 0x47], 0x1005, true)]
+    // nop; nop
+    #[case(&[0x00, 0xbf, 0x00, 0xbf], 0x1005, false)]
+    // address outside `.text`
+    #[case(&[0x00, 0xf0, 0x08, 0xf8], 0x2005, false)]
+    fn follows_call(#[case] data: &[u8], #[case] return_address: u32, #[case] expected: bool) {
+        let text = Text {
+            start: 0x1000,
+            data,
+        };
+
+        assert_eq!(expected, text.follows_call(return_address));
+    }
+}
@@ -0,0 +1,160 @@
+//! Source code snippets for backtrace frames
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::Command,
+    str,
+};
+
+use colored::Colorize as _;
+
+use crate::dep;
+
+use super::symbolicate::Location;
+
+/// Loads (and caches) the source files backtrace frames point into
+pub(crate) struct Sources<'p> {
+    current_dir: &'p Path,
+    files: HashMap<PathBuf, Option<Vec<String>>>,
+    /// `None` = not yet queried; `Some(None)` = `rustc` is not available
+    sysroot: Option<Option<PathBuf>>,
+}
+
+impl<'p> Sources<'p> {
+    pub(crate) fn new(current_dir: &'p Path) -> Self {
+        Self {
+            current_dir,
+            files: HashMap::new(),
+            sysroot: None,
+        }
+    }
+
+    /// Prints `context` lines above and below `location`, highlighting the line `location`
+    /// points at
+    ///
+    /// Prints nothing if the source file can't be found on this machine
+    pub(crate) fn print_snippet(
+        &mut self,
+        w: &mut impl Write,
+        location: &Location,
+        context: u32,
+    ) -> io::Result<()> {
+        let path = match self.resolve(location) {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let lines = self
+            .files
+            .entry(path)
+            .or_insert_with_key(|path| {
+                fs::read_to_string(path)
+                    .ok()
+                    .map(|contents| contents.lines().map(str::to_owned).collect())
+            })
+            .as_deref();
+        let lines = match lines {
+            Some(lines) => lines,
+            None => return Ok(()),
+        };
+
+        let range = match snippet_range(lines.len(), location.line, context) {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        let width = range.end().to_string().len();
+        for number in range {
+            let text = &lines[number as usize - 1];
+            if number == location.line {
+                writeln!(
+                    w,
+                    "{}",
+                    format!("        > {:>width$} | {}", number, text, width = width).bold()
+                )?;
+            } else {
+                writeln!(
+                    w,
+                    "{}",
+                    format!("          {:>width$} | {}", number, text, width = width).dimmed()
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the location's source file on this machine
+    pub(crate) fn resolve(&mut self, location: &Location) -> Option<PathBuf> {
+        if location.path_is_relative {
+            return Some(self.current_dir.join(&location.path));
+        }
+
+        if location.path.exists() {
+            return Some(location.path.clone());
+        }
+
+        // remapped `/rustc/$hash` paths can be found in the `rust-src` component
+        if let dep::Path::Rustc(rustc) = dep::Path::from_std_path(&location.path) {
+            let sysroot = self.sysroot.get_or_insert_with(sysroot).as_ref()?;
+            let path = sysroot
+                .join("lib/rustlib/src/rust")
+                .join(rustc.rust_repo_path());
+            if path.exists() {
+                return Some(path);
+            }
+        }
+
+        None
+    }
+}
+
+fn sysroot() -> Option<PathBuf> {
+    let output = Command::new("rustc")
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(PathBuf::from(str::from_utf8(&output.stdout).ok()?.trim()))
+}
+
+/// Returns the (1-based) line numbers to print, or `None` if `line` is not in the file
+fn snippet_range(num_lines: usize, line: u32, context: u32) -> Option<RangeInclusive<u32>> {
+    let num_lines = num_lines as u32;
+    if line == 0 || line > num_lines {
+        return None;
+    }
+
+    let start = line.saturating_sub(context).max(1);
+    let end = line.saturating_add(context).min(num_lines);
+    Some(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(100, 50, 2, Some(48..=52))]
+    #[case(100, 1, 2, Some(1..=3))]
+    #[case(100, 100, 2, Some(98..=100))]
+    #[case(100, 101, 2, None)]
+    #[case(100, 0, 2, None)]
+    #[case(100, 50, u32::MAX, Some(1..=100))]
+    fn snippet_ranges(
+        #[case] num_lines: usize,
+        #[case] line: u32,
+        #[case] context: u32,
+        #[case] expected: Option<RangeInclusive<u32>>,
+    ) {
+        assert_eq!(expected, snippet_range(num_lines, line, context));
+    }
+}
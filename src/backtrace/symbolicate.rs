@@ -13,13 +13,17 @@ use object::{read::File as ElfFile, Object as _, SymbolMap, SymbolMapName};
 
 use crate::cortexm;
 
-use super::unwind::RawFrame;
+use super::{
+    locals::{Locals, Variable},
+    unwind::RawFrame,
+};
 
 pub(crate) fn frames(
     raw_frames: &[RawFrame],
     live_functions: &HashSet<&str>,
     current_dir: &Path,
     elf: &ElfFile,
+    mut locals: Option<Locals>,
 ) -> Vec<Frame> {
     let mut frames = vec![];
 
@@ -30,17 +34,37 @@ pub(crate) fn frames(
         match raw_frame {
             RawFrame::Exception => frames.push(Frame::Exception),
 
-            RawFrame::Subroutine { pc } => {
-                for subroutine in Subroutine::from_pc(
+            RawFrame::Subroutine { pc, registers } => {
+                let mut scopes = locals
+                    .as_mut()
+                    .map(|locals| locals.scopes(*pc, registers))
+                    .unwrap_or_default()
+                    .into_iter();
+
+                for mut subroutine in Subroutine::from_pc(
                     *pc,
                     addr2line.as_ref(),
                     live_functions,
                     current_dir,
                     &symtab,
                 ) {
+                    // NOTE both lists are ordered innermost (inlined) function first
+                    subroutine.variables = scopes.next().unwrap_or_default();
                     frames.push(Frame::Subroutine(subroutine))
                 }
             }
+
+            RawFrame::Probable { pc } => {
+                for subroutine in Subroutine::from_pc(
+                    *pc,
+                    addr2line.as_ref(),
+                    live_functions,
+                    current_dir,
+                    &symtab,
+                ) {
+                    frames.push(Frame::Probable(subroutine))
+                }
+            }
         }
     }
 
@@ -52,6 +76,8 @@ pub(crate) fn frames(
 pub(crate) enum Frame {
     Exception,
     Subroutine(Subroutine),
+    /// Subroutine recovered by scanning the stack
+    Probable(Subroutine),
 }
 
 /// "Symbolicated" and de-inlined subroutine frame
@@ -59,6 +85,8 @@ pub(crate) enum Frame {
 pub(crate) struct Subroutine {
     pub(crate) name_or_pc: Either<String, u32>,
     pub(crate) location: Option<Location>,
+    /// Arguments and local variables; only populated with `--show-locals`
+    pub(crate) variables: Vec<Variable>,
 }
 
 type A2lContext = addr2line::Context<EndianReader<RunTimeEndian, Rc<[u8]>>>;
@@ -122,7 +150,7 @@ impl Subroutine {
                         .and_then(|file| loc.line.map(|line| (file, line, loc.column)))
                 }) {
                 let fullpath = Path::new(file);
-                let (path, is_local) = if let Ok(relpath) = fullpath.strip_prefix(&current_dir) {
+                let (path, is_local) = if let Ok(relpath) = fullpath.strip_prefix(current_dir) {
                     (relpath, true)
                 } else {
                     (fullpath, false)
@@ -141,6 +169,7 @@ impl Subroutine {
             subroutines.push(Subroutine {
                 name_or_pc,
                 location,
+                variables: vec![],
             })
         }
 
@@ -151,6 +180,7 @@ impl Subroutine {
         Subroutine {
             name_or_pc: name_from_symtab(pc, symtab),
             location: None,
+            variables: vec![],
         }
     }
 }
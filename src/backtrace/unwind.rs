@@ -1,62 +1,134 @@
 //! unwind target's program
 
-use anyhow::{bail, ensure, Context as _};
+use std::fmt;
+
 use gimli::{
     BaseAddresses, DebugFrame, LittleEndian, UninitializedUnwindContext, UnwindSection as _,
 };
-use probe_rs::{config::RamRegion, Core};
+use probe_rs::{config::RamRegion, Core, MemoryInterface as _};
 
 use crate::{
+    arch::Arch,
     cortexm,
-    registers::{self, Registers},
+    registers::{FrameRegisters, Registers},
+    riscv,
     stacked::Stacked,
-    Outcome, VectorTable,
+    Outcome,
 };
 
-static MISSING_DEBUG_INFO: &str = "debug information is missing. Likely fixes:
+static MISSING_DEBUG_INFO_FIXES: &str = "Likely fixes:
 1. compile the Rust code with `debug = 1` or higher. This is configured in the `profile.{release,bench}` sections of Cargo.toml (`profile.{dev,test}` default to `debug = 2`)
 2. use a recent version of the `cortex-m` crates (e.g. cortex-m 0.6.3 or newer). Check versions in Cargo.lock
 3. if linking to C code, compile the C code with the `-g` flag";
 
 /// Virtually* unwinds the target's program
 /// \* destructors are not run
-// FIXME(?) this should be "infallible" and return as many frames as possible even in case of IO
-// errors
+///
+/// This only fails if the registers of the halted core can't be read at all; if unwinding can't be
+/// completed the frames collected so far are returned together with the reason why unwinding
+/// stopped
 pub(crate) fn target(
     core: &mut Core,
     debug_frame: &[u8],
-    vector_table: &VectorTable,
+    arch: &Arch,
     sp_ram_region: &Option<RamRegion>,
 ) -> anyhow::Result<Output> {
+    let register_file = core.registers();
+    let pc = core.read_core_reg(register_file.program_counter())?;
+    let sp = core.read_core_reg(register_file.stack_pointer())?;
+    let lr = core.read_core_reg(register_file.return_address())?;
+
+    let mut output = Output {
+        outcome: Outcome::Ok,
+        raw_frames: vec![],
+        sp: Some(sp),
+        error: None,
+    };
+
+    let registers = Registers::new(lr, sp, arch, core);
+    if let Err(e) = unwind(
+        debug_frame,
+        arch,
+        sp_ram_region,
+        pc,
+        sp,
+        registers,
+        &mut output,
+    ) {
+        output.error = Some(e);
+    }
+
+    Ok(output)
+}
+
+fn unwind(
+    debug_frame: &[u8],
+    arch: &Arch,
+    sp_ram_region: &Option<RamRegion>,
+    mut pc: u32,
+    sp: u32,
+    mut registers: Registers,
+    output: &mut Output,
+) -> Result<(), UnwindError> {
     let mut debug_frame = DebugFrame::new(debug_frame, LittleEndian);
-    debug_frame.set_address_size(cortexm::ADDRESS_SIZE);
+    debug_frame.set_address_size(arch.address_size());
 
-    let mut pc = core.read_core_reg(registers::PC)?;
-    let sp = core.read_core_reg(registers::SP)?;
-    let lr = core.read_core_reg(registers::LR)?;
     let base_addresses = BaseAddresses::default();
     let mut unwind_context = UninitializedUnwindContext::new();
 
-    let mut outcome = Outcome::Ok;
-    let mut registers = Registers::new(lr, sp, core);
-    let mut raw_frames = vec![];
-    let mut corrupted = true;
+    let raw_frames = &mut output.raw_frames;
+    // only the first frame(s) can read the remaining registers straight from the core
+    let mut is_top = true;
 
     loop {
-        if cortexm::is_hard_fault(pc, vector_table) {
-            assert!(
-                raw_frames.is_empty(),
-                "when present HardFault handler must be the first frame we unwind but wasn't"
-            );
-
-            outcome = if overflowed_stack(sp, sp_ram_region) {
+        if arch.is_fault_handler(pc) {
+            output.outcome = if overflowed_stack(sp, sp_ram_region) {
                 Outcome::StackOverflow
             } else {
                 Outcome::HardFault
             };
+
+            if !raw_frames.is_empty() {
+                // e.g. a fault inside the fault handler; the frames below it can't be trusted
+                raw_frames.push(RawFrame::Subroutine {
+                    pc,
+                    registers: registers.snapshot(is_top),
+                });
+                return Err(UnwindError::NestedFault { pc });
+            }
+
+            if let Arch::Riscv(_) = arch {
+                // we halted on the entry of `ExceptionHandler`, whose argument points to the trap
+                // frame; the trapped subroutine is found at `mepc`
+                raw_frames.push(RawFrame::Subroutine {
+                    pc,
+                    registers: registers.snapshot(is_top),
+                });
+                raw_frames.push(RawFrame::Exception);
+
+                let mcause = registers.core.read_core_reg(riscv::MCAUSE)?;
+                log::error!(
+                    "trap: {} (mcause={:#010X})",
+                    riscv::exception_description(mcause),
+                    mcause
+                );
+
+                let trap_frame = registers.get(riscv::A0)?;
+                let ra = registers.core.read_word_32(trap_frame)?;
+                registers.insert(riscv::RA, ra);
+                registers.insert(riscv::SP, trap_frame + riscv::TRAP_FRAME_SIZE);
+                output.sp = Some(trap_frame + riscv::TRAP_FRAME_SIZE);
+                is_top = false;
+
+                pc = registers.core.read_core_reg(riscv::MEPC)?;
+                continue;
+            }
         }
 
-        raw_frames.push(RawFrame::Subroutine { pc });
+        raw_frames.push(RawFrame::Subroutine {
+            pc,
+            registers: registers.snapshot(is_top),
+        });
 
         let uwt_row = debug_frame
             .unwind_info_for_address(
@@ -65,93 +137,173 @@ pub(crate) fn target(
                 pc.into(),
                 DebugFrame::cie_from_offset,
             )
-            .with_context(|| MISSING_DEBUG_INFO)?;
+            .map_err(|_| UnwindError::MissingDebugInfo { pc })?;
 
         let cfa_changed = registers.update_cfa(uwt_row.cfa())?;
+        let cfa = registers.get(arch.stack_pointer())?;
+        output.sp = Some(cfa);
+        if let Some(RawFrame::Subroutine { registers, .. }) = raw_frames.last_mut() {
+            registers.cfa = Some(cfa);
+        }
+        is_top = false;
 
         for (reg, rule) in uwt_row.registers() {
             registers.update(reg, rule)?;
         }
 
-        let lr = registers.get(registers::LR)?;
+        let lr = registers.get(arch.return_address())?;
 
         log::debug!("LR={:#010X} PC={:#010X}", lr, pc);
 
-        if lr == registers::LR_END {
-            break;
+        if lr == arch.return_address_end() {
+            return Ok(());
         }
 
-        // Link Register contains an EXC_RETURN value. This deliberately also includes
-        // invalid combinations of final bits 0-4 to prevent futile backtrace re-generation attempts
-        let exception_entry = lr >= cortexm::EXC_RETURN_MARKER;
-
-        let program_counter_changed = !cortexm::subroutine_eq(lr, pc);
+        let program_counter_changed = !arch.subroutine_eq(lr, pc);
 
         // If the frame didn't move, and the program counter didn't change, bail out (otherwise we
         // might print the same frame over and over).
-        corrupted = !cfa_changed && !program_counter_changed;
+        if !cfa_changed && !program_counter_changed {
+            return Err(UnwindError::CorruptedStack);
+        }
 
-        if corrupted {
-            break;
+        if let Arch::Riscv(_) = arch {
+            pc = lr;
+            continue;
         }
 
+        // Link Register contains an EXC_RETURN value. This deliberately also includes
+        // invalid combinations of final bits 0-4 to prevent futile backtrace re-generation attempts
+        let exception_entry = lr >= cortexm::EXC_RETURN_MARKER;
+
         if exception_entry {
             raw_frames.push(RawFrame::Exception);
 
             let fpu = match lr {
                 0xFFFFFFF1 | 0xFFFFFFF9 | 0xFFFFFFFD => false,
                 0xFFFFFFE1 | 0xFFFFFFE9 | 0xFFFFFFED => true,
-                _ => bail!("LR contains invalid EXC_RETURN value {:#010X}", lr),
+                _ => return Err(UnwindError::InvalidExcReturn { lr }),
             };
 
-            let sp = registers.get(registers::SP)?;
+            let sp = registers.get(cortexm::SP)?;
             let ram_bounds = sp_ram_region
                 .as_ref()
                 .map(|ram_region| ram_region.range.clone())
                 .unwrap_or(cortexm::VALID_RAM_ADDRESS);
-            let stacked = if let Some(stacked) = Stacked::read(registers.core, sp, fpu, ram_bounds)?
-            {
-                stacked
-            } else {
-                corrupted = true;
-                break;
-            };
+            let stacked = Stacked::read(registers.core, sp, fpu, ram_bounds)?
+                .ok_or(UnwindError::StackedRegistersOutOfBounds { sp })?;
 
-            registers.insert(registers::LR, stacked.lr);
+            registers.insert(cortexm::LR, stacked.lr);
             // adjust the stack pointer for stacked registers
-            registers.insert(registers::SP, sp + stacked.size());
+            registers.insert(cortexm::SP, sp + stacked.size());
+            output.sp = Some(sp + stacked.size());
 
             pc = stacked.pc;
         } else {
-            ensure!(
-                cortexm::is_thumb_bit_set(lr),
-                "bug? LR ({:#010x}) didn't have the Thumb bit set",
-                lr
-            );
+            if !cortexm::is_thumb_bit_set(lr) {
+                return Err(UnwindError::ThumbBitNotSet { lr });
+            }
 
             pc = cortexm::clear_thumb_bit(lr);
         }
     }
-
-    Ok(Output {
-        corrupted,
-        outcome,
-        raw_frames,
-    })
 }
 
 #[derive(Debug)]
 pub struct Output {
-    pub(crate) corrupted: bool,
     pub(crate) outcome: Outcome,
     pub(crate) raw_frames: Vec<RawFrame>,
+    /// Value of the stack pointer in the last frame we unwound
+    pub(crate) sp: Option<u32>,
+    /// Reason why unwinding stopped before reaching the end of the call stack
+    pub(crate) error: Option<UnwindError>,
+}
+
+/// Reason why unwinding could not be completed
+#[derive(Debug)]
+pub(crate) enum UnwindError {
+    /// Neither the frame nor the program counter changed between two frames
+    CorruptedStack,
+    /// The registers stacked on exception entry lie outside the RAM region
+    StackedRegistersOutOfBounds {
+        sp: u32,
+    },
+    InvalidExcReturn {
+        lr: u32,
+    },
+    ThumbBitNotSet {
+        lr: u32,
+    },
+    MissingDebugInfo {
+        pc: u32,
+    },
+    /// The fault handler was found below the first frame, e.g. because it faulted itself
+    NestedFault {
+        pc: u32,
+    },
+    /// The debug information uses an unwinding rule that is not implemented
+    UnsupportedRule(String),
+    /// Reading the target's registers or memory failed
+    Probe(anyhow::Error),
+}
+
+impl fmt::Display for UnwindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnwindError::CorruptedStack => f.write_str("call stack was corrupted"),
+            UnwindError::StackedRegistersOutOfBounds { sp } => write!(
+                f,
+                "registers stacked on exception entry (SP={:#010X}) lie outside of RAM",
+                sp
+            ),
+            UnwindError::InvalidExcReturn { lr } => {
+                write!(f, "LR contains invalid EXC_RETURN value {:#010X}", lr)
+            }
+            UnwindError::ThumbBitNotSet { lr } => {
+                write!(f, "bug? LR ({:#010x}) didn't have the Thumb bit set", lr)
+            }
+            UnwindError::MissingDebugInfo { pc } => write!(
+                f,
+                "debug information for address {:#x} is missing. {}",
+                pc, MISSING_DEBUG_INFO_FIXES
+            ),
+            UnwindError::NestedFault { pc } => write!(
+                f,
+                "the fault handler ({:#010x}) was reached again while unwinding; nested fault?",
+                pc
+            ),
+            UnwindError::UnsupportedRule(rule) => {
+                write!(f, "the debug information uses an unsupported {}", rule)
+            }
+            UnwindError::Probe(e) => write!(f, "failed to read the target's state: {:#}", e),
+        }
+    }
+}
+
+impl From<anyhow::Error> for UnwindError {
+    fn from(e: anyhow::Error) -> Self {
+        UnwindError::Probe(e)
+    }
+}
+
+impl From<probe_rs::Error> for UnwindError {
+    fn from(e: probe_rs::Error) -> Self {
+        UnwindError::Probe(e.into())
+    }
 }
 
 /// Backtrace frame prior to 'symbolication'
 #[derive(Debug)]
 pub(crate) enum RawFrame {
-    Subroutine { pc: u32 },
+    Subroutine {
+        pc: u32,
+        registers: FrameRegisters,
+    },
     Exception,
+    /// Subroutine found by scanning the stack; may not be part of the actual call stack
+    Probable {
+        pc: u32,
+    },
 }
 
 impl RawFrame {
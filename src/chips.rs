@@ -0,0 +1,231 @@
+//! Searching the chips supported by `probe-rs`
+
+use anyhow::anyhow;
+use probe_rs::config::{registry, ChipFamily, MemoryRegion};
+use serde::Serialize;
+
+use crate::OutputFormat;
+
+/// Maximum number of "did you mean" suggestions
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Prints the chips whose (family) name contains `pattern`, or all chips
+pub(crate) fn print(pattern: Option<&str>, format: OutputFormat) -> anyhow::Result<()> {
+    let families = registry::families()?;
+    let matches = families
+        .iter()
+        .filter_map(|family| {
+            let variants = family
+                .variants
+                .iter()
+                .filter(|variant| match pattern {
+                    Some(pattern) => {
+                        matches(&family.name, pattern) || matches(&variant.name, pattern)
+                    }
+                    None => true,
+                })
+                .collect::<Vec<_>>();
+            if variants.is_empty() {
+                None
+            } else {
+                Some((family, variants))
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match format {
+        OutputFormat::Text => {
+            if matches.is_empty() {
+                println!("No chips match `{}`.", pattern.unwrap_or_default());
+            }
+            for (family, variants) in &matches {
+                println!("{}\n    Variants:", family.name);
+                for variant in variants {
+                    println!("        {}", variant.name);
+                }
+            }
+        }
+
+        OutputFormat::Json => {
+            let chips = matches
+                .iter()
+                .flat_map(|(family, variants)| {
+                    variants.iter().map(move |variant| ChipJson {
+                        name: &variant.name,
+                        family: &family.name,
+                        core: &family.core,
+                        memory_map: variant
+                            .memory_map
+                            .iter()
+                            .map(MemoryRegionJson::from)
+                            .collect(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::to_string_pretty(&chips)?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns a failed chip lookup into an error that suggests similarly named chips
+pub(crate) fn not_found(name: &str) -> anyhow::Error {
+    let names = registry::families()
+        .map(|families| variant_names(&families))
+        .unwrap_or_default();
+    let suggestions = suggestions(name, &names);
+
+    let hint = if suggestions.is_empty() {
+        String::new()
+    } else {
+        let suggestions = suggestions
+            .iter()
+            .map(|suggestion| format!("`{}`", suggestion))
+            .collect::<Vec<_>>();
+        format!("; did you mean {}?", suggestions.join(" or "))
+    };
+    anyhow!(
+        "chip `{}` not found{}\nnote: run `probe-run --list-chips <pattern>` to search the supported chips",
+        name,
+        hint
+    )
+}
+
+fn variant_names(families: &[ChipFamily]) -> Vec<String> {
+    families
+        .iter()
+        .flat_map(|family| family.variants.iter())
+        .map(|variant| variant.name.to_string())
+        .collect()
+}
+
+/// Case-insensitive substring match
+fn matches(name: &str, pattern: &str) -> bool {
+    name.to_lowercase().contains(&pattern.to_lowercase())
+}
+
+/// Returns the chip names closest to `name`, best match first
+fn suggestions<'n>(name: &str, names: &'n [String]) -> Vec<&'n str> {
+    let name = name.to_lowercase();
+
+    // chip names are often given without their package suffix, e.g. `52840` for `nRF52840_xxAA`
+    let mut containing = names
+        .iter()
+        .filter(|candidate| candidate.to_lowercase().contains(&name))
+        .map(|candidate| &**candidate)
+        .collect::<Vec<_>>();
+    if !containing.is_empty() {
+        containing.sort_by_key(|candidate| candidate.len());
+        containing.truncate(MAX_SUGGESTIONS);
+        return containing;
+    }
+
+    // otherwise assume a typo, e.g. `nrf52480` for `nRF52840_xxAA`
+    let max_distance = (name.chars().count() / 3).max(2);
+    let mut scored = names
+        .iter()
+        .filter_map(|candidate| {
+            let candidate_lower = candidate.to_lowercase();
+            let prefix = candidate_lower
+                .chars()
+                .take(name.chars().count())
+                .collect::<String>();
+            let distance = levenshtein(&name, &candidate_lower).min(levenshtein(&name, &prefix));
+            if distance <= max_distance {
+                Some((distance, &**candidate))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    scored.sort();
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Edit distance between two strings
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[derive(Serialize)]
+struct ChipJson<'a> {
+    name: &'a str,
+    family: &'a str,
+    core: &'a str,
+    memory_map: Vec<MemoryRegionJson>,
+}
+
+#[derive(Serialize)]
+struct MemoryRegionJson {
+    kind: &'static str,
+    start: u32,
+    end: u32,
+}
+
+impl From<&MemoryRegion> for MemoryRegionJson {
+    fn from(region: &MemoryRegion) -> Self {
+        let (kind, range) = match region {
+            MemoryRegion::Ram(ram) => ("ram", &ram.range),
+            MemoryRegion::Generic(generic) => ("generic", &generic.range),
+            MemoryRegion::Flash(flash) => ("flash", &flash.range),
+        };
+        Self {
+            kind,
+            start: range.start,
+            end: range.end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    const NAMES: &[&str] = &[
+        "nRF52832_xxAA",
+        "nRF52833_xxAA",
+        "nRF52840_xxAA",
+        "STM32F401RETx",
+        "STM32F411RETx",
+    ];
+
+    #[rstest]
+    #[case("52840", &["nRF52840_xxAA"])]
+    #[case("nrf5283", &["nRF52832_xxAA", "nRF52833_xxAA"])]
+    #[case("nrf52480", &["nRF52840_xxAA"])]
+    #[case("stm32f410re", &["STM32F411RETx", "STM32F401RETx"])]
+    #[case("esp32", &[])]
+    fn chip_suggestions(#[case] name: &str, #[case] expected: &[&str]) {
+        let names = NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, &*suggestions(name, &names));
+    }
+
+    #[rstest]
+    #[case("", "abc", 3)]
+    #[case("kitten", "sitting", 3)]
+    #[case("nrf52840", "nrf52840", 0)]
+    fn levenshtein_distances(#[case] a: &str, #[case] b: &str, #[case] expected: usize) {
+        assert_eq!(expected, levenshtein(a, b));
+    }
+}
@@ -0,0 +1,317 @@
+//! Project configuration read from `probe-run.toml` or `Cargo.toml` files
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+/// Name of the dedicated configuration file
+const FILE_NAME: &str = "probe-run.toml";
+
+/// Settings that can be set per project instead of on the command line
+///
+/// Keys use the names of the equivalent command line flags
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Config {
+    pub(crate) chip: Option<String>,
+    /// Relative paths are resolved against the directory of the configuration file
+    pub(crate) chip_description_path: Option<PathBuf>,
+    pub(crate) probe: Option<String>,
+    pub(crate) speed: Option<u32>,
+    pub(crate) connect_under_reset: Option<bool>,
+    pub(crate) ram: Option<bool>,
+    pub(crate) verify: Option<bool>,
+    pub(crate) backtrace: Option<String>,
+    pub(crate) force_backtrace: Option<bool>,
+    pub(crate) max_backtrace_len: Option<u32>,
+    pub(crate) shorten_paths: Option<bool>,
+    pub(crate) scan_stack: Option<bool>,
+    pub(crate) source_context: Option<u32>,
+    pub(crate) show_locals: Option<bool>,
+    pub(crate) hyperlinks: Option<Hyperlinks>,
+    pub(crate) defmt_filter: Option<String>,
+    /// `elapsed` or `wall`
+    pub(crate) host_timestamps: Option<String>,
+    pub(crate) log_format: Option<String>,
+    /// Names for probe filters, e.g. `board-a = "0483:374b:0671FF"`
+    pub(crate) probe_aliases: BTreeMap<String, String>,
+    pub(crate) rtt: Rtt,
+    pub(crate) timeouts: Timeouts,
+}
+
+/// `hyperlinks = true` or `hyperlinks = "<template>"`
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub(crate) enum Hyperlinks {
+    Enabled(bool),
+    Template(String),
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Rtt {
+    /// Index of the up channel the logs are read from
+    pub(crate) channel: Option<usize>,
+    /// How often to retry attaching to the RTT control block
+    pub(crate) attach_retries: Option<usize>,
+    /// Modes of the up channels, like `--rtt-mode`
+    pub(crate) mode: Option<String>,
+    /// `breakpoint` or `poll`
+    pub(crate) attach: Option<String>,
+    /// Symbol the program is stopped at before attaching, `main` by default
+    pub(crate) init_symbol: Option<String>,
+    /// Start and end address of the memory that is searched for the control block if the program
+    /// has no `_SEGGER_RTT` symbol
+    pub(crate) scan_range: Option<[u32; 2]>,
+}
+
+/// Timeouts in milliseconds
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub(crate) struct Timeouts {
+    /// For halting (and resetting) the core
+    pub(crate) halt: Option<u64>,
+    /// For the program to reach `main`
+    pub(crate) main: Option<u64>,
+}
+
+impl Config {
+    /// Loads and merges all configuration files found in the directories above the Cargo package
+    /// being run (`CARGO_MANIFEST_DIR`), the current directory and the ELF file (if any)
+    ///
+    /// Settings in files closer to those directories take precedence
+    pub(crate) fn load(elf: Option<&Path>) -> anyhow::Result<Self> {
+        let mut start_dirs = vec![];
+        if let Some(manifest_dir) = env::var_os("CARGO_MANIFEST_DIR") {
+            start_dirs.push(PathBuf::from(manifest_dir));
+        }
+        start_dirs.push(env::current_dir()?);
+        if let Some(elf) = elf {
+            if let Some(elf_dir) = elf.canonicalize()?.parent() {
+                start_dirs.push(elf_dir.to_owned());
+            }
+        }
+
+        let mut config = Config::default();
+        let mut visited = HashSet::new();
+        for start_dir in &start_dirs {
+            for dir in start_dir.ancestors() {
+                // NOTE the ancestors of a visited directory have been visited (or skipped) too
+                if !visited.insert(dir.to_owned()) {
+                    break;
+                }
+
+                let path = dir.join(FILE_NAME);
+                if path.is_file() {
+                    let found = Self::from_file(&fs::read_to_string(&path)?)
+                        .with_context(|| format!("invalid configuration in {}", path.display()))?;
+                    config = config.or(found.relative_to(dir, &path));
+                }
+
+                let path = dir.join("Cargo.toml");
+                if path.is_file() {
+                    let manifest = match toml::from_str(&fs::read_to_string(&path)?) {
+                        Ok(manifest) => manifest,
+                        // unrelated manifests, e.g. of a parent project, must not break `probe-run`;
+                        // `cargo` reports the errors of the manifests it uses
+                        Err(e) => {
+                            log::warn!("skipping invalid manifest {}: {}", path.display(), e);
+                            continue;
+                        }
+                    };
+                    let (found, is_workspace_root) = Self::from_manifest(manifest)
+                        .with_context(|| format!("invalid configuration in {}", path.display()))?;
+                    if let Some(found) = found {
+                        config = config.or(found.relative_to(dir, &path));
+                    }
+                    if is_workspace_root {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Resolves the relative paths of a configuration found in `file` in `dir`
+    fn relative_to(mut self, dir: &Path, file: &Path) -> Self {
+        if let Some(path) = &mut self.chip_description_path {
+            *path = dir.join(&*path);
+        }
+        log::debug!("using configuration from {}", file.display());
+        self
+    }
+
+    fn from_file(contents: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Reads the `[package.metadata.probe-run]` or `[workspace.metadata.probe-run]` table, and
+    /// whether the manifest is the root of a workspace
+    fn from_manifest(
+        manifest: toml::Value,
+    ) -> anyhow::Result<(Option<Self>, /* is workspace root: */ bool)> {
+        let config = |table: &str| -> anyhow::Result<Option<Config>> {
+            let config = manifest
+                .get(table)
+                .and_then(|table| table.get("metadata"))
+                .and_then(|metadata| metadata.get("probe-run"));
+            Ok(match config {
+                Some(config) => Some(config.clone().try_into()?),
+                None => None,
+            })
+        };
+        let config = match (config("package")?, config("workspace")?) {
+            (Some(package), Some(workspace)) => Some(package.or(workspace)),
+            (package, workspace) => package.or(workspace),
+        };
+        Ok((config, manifest.get("workspace").is_some()))
+    }
+
+    /// Fills the settings missing in `self` with the ones of `other`
+    fn or(self, other: Self) -> Self {
+        let mut probe_aliases = other.probe_aliases;
+        probe_aliases.extend(self.probe_aliases);
+
+        Self {
+            chip: self.chip.or(other.chip),
+            chip_description_path: self.chip_description_path.or(other.chip_description_path),
+            probe: self.probe.or(other.probe),
+            speed: self.speed.or(other.speed),
+            connect_under_reset: self.connect_under_reset.or(other.connect_under_reset),
+            ram: self.ram.or(other.ram),
+            verify: self.verify.or(other.verify),
+            backtrace: self.backtrace.or(other.backtrace),
+            force_backtrace: self.force_backtrace.or(other.force_backtrace),
+            max_backtrace_len: self.max_backtrace_len.or(other.max_backtrace_len),
+            shorten_paths: self.shorten_paths.or(other.shorten_paths),
+            scan_stack: self.scan_stack.or(other.scan_stack),
+            source_context: self.source_context.or(other.source_context),
+            show_locals: self.show_locals.or(other.show_locals),
+            hyperlinks: self.hyperlinks.or(other.hyperlinks),
+            defmt_filter: self.defmt_filter.or(other.defmt_filter),
+            host_timestamps: self.host_timestamps.or(other.host_timestamps),
+            log_format: self.log_format.or(other.log_format),
+            probe_aliases,
+            rtt: Rtt {
+                channel: self.rtt.channel.or(other.rtt.channel),
+                attach_retries: self.rtt.attach_retries.or(other.rtt.attach_retries),
+                mode: self.rtt.mode.or(other.rtt.mode),
+                attach: self.rtt.attach.or(other.rtt.attach),
+                init_symbol: self.rtt.init_symbol.or(other.rtt.init_symbol),
+                scan_range: self.rtt.scan_range.or(other.rtt.scan_range),
+            },
+            timeouts: Timeouts {
+                halt: self.timeouts.halt.or(other.timeouts.halt),
+                main: self.timeouts.main.or(other.timeouts.main),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn file() {
+        let contents = r#"
+chip = "nRF52840_xxAA"
+speed = 4000
+hyperlinks = "vscode://file{path}:{line}:{col}"
+
+[rtt]
+channel = 1
+mode = "no-block-trim"
+scan-range = [0x2000_0000, 0x2000_4000]
+
+[timeouts]
+halt = 2000
+"#;
+
+        let expected = Config {
+            chip: Some("nRF52840_xxAA".to_string()),
+            speed: Some(4000),
+            hyperlinks: Some(Hyperlinks::Template(
+                "vscode://file{path}:{line}:{col}".to_string(),
+            )),
+            rtt: Rtt {
+                channel: Some(1),
+                attach_retries: None,
+                mode: Some("no-block-trim".to_string()),
+                scan_range: Some([0x2000_0000, 0x2000_4000]),
+                ..Rtt::default()
+            },
+            timeouts: Timeouts {
+                halt: Some(2000),
+                main: None,
+            },
+            ..Config::default()
+        };
+        assert_eq!(expected, Config::from_file(contents).unwrap());
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert!(Config::from_file("chipp = \"nRF52840_xxAA\"").is_err());
+    }
+
+    #[test]
+    fn manifest() {
+        let contents = r#"
+[package]
+name = "app"
+
+[package.metadata.probe-run]
+chip = "nRF52840_xxAA"
+
+[workspace.metadata.probe-run]
+chip = "STM32F401RE"
+probe = "0483:3748"
+"#;
+
+        let expected = Config {
+            chip: Some("nRF52840_xxAA".to_string()),
+            probe: Some("0483:3748".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            (Some(expected), true),
+            Config::from_manifest(toml::from_str(contents).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn manifest_without_config() {
+        let contents = r#"
+[package]
+name = "app"
+
+[package.metadata.docs.rs]
+all-features = true
+"#;
+
+        assert_eq!(
+            (None, false),
+            Config::from_manifest(toml::from_str(contents).unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn manifest_with_invalid_config() {
+        let contents = r#"
+[package.metadata.probe-run]
+chipp = "nRF52840_xxAA"
+"#;
+
+        assert!(Config::from_manifest(toml::from_str(contents).unwrap()).is_err());
+    }
+}
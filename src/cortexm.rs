@@ -2,11 +2,33 @@
 
 use std::{mem, ops::Range};
 
+use anyhow::bail;
+use gimli::Register;
+use probe_rs::{Core, CoreRegisterAddress, MemoryInterface as _};
+
 use crate::VectorTable;
 
 pub(crate) const ADDRESS_SIZE: u8 = mem::size_of::<u32>() as u8;
 pub(crate) const EXC_RETURN_MARKER: u32 = 0xFFFF_FFE0;
+/// DWARF number of the Link Register
+pub(crate) const LR: Register = Register(14);
+/// Value of the Link Register when `Reset` is entered
+pub(crate) const LR_END: u32 = 0xFFFF_FFFF;
+/// DWARF number of the Program Counter
+pub(crate) const PC: Register = Register(15);
+/// DWARF number of the Stack Pointer
+pub(crate) const SP: Register = Register(13);
 const THUMB_BIT: u32 = 1;
+/// Vector Table Offset Register
+const VTOR: u32 = 0xE000_ED08;
+/// Debug Exception and Monitor Control Register
+const DEMCR: u32 = 0xE000_EDFC;
+/// Bit of DEMCR that halts the core when it enters the HardFault handler
+const DEMCR_VC_HARDERR: u32 = 1 << 10;
+/// Flash Patch and Breakpoint Control Register (`BP_CTRL` of the ARMv6-M Breakpoint Unit)
+const FP_CTRL: u32 = 0xE000_2000;
+/// The breakpoint comparators of FPB revision 1 (and of the BPU) only match the code region
+const CODE_REGION_END: u32 = 0x2000_0000;
 // According to the ARM Cortex-M Reference Manual RAM memory must be located in this address range
 // (vendors still place e.g. Core-Coupled RAM outside this address range)
 pub(crate) const VALID_RAM_ADDRESS: Range<u32> = 0x2000_0000..0x4000_0000;
@@ -15,6 +37,60 @@ pub(crate) fn clear_thumb_bit(addr: u32) -> u32 {
     addr & !THUMB_BIT
 }
 
+/// Points the halted core at the vector table `vector_table` and sets SP and PC as the hardware
+/// would on reset
+pub(crate) fn boot_from(core: &mut Core, vector_table: &VectorTable) -> anyhow::Result<()> {
+    core.write_word_32(VTOR, vector_table.location)?;
+    // NOTE VTOR is optional on ARMv6-M (e.g. Cortex-M0); where it's missing, writes are ignored
+    let vtor = core.read_word_32(VTOR)?;
+    if vtor != vector_table.location {
+        bail!(
+            "the device ignored the vector table address 0x{:08X} (VTOR reads 0x{:08X}); \
+            it can't run programs from RAM -- remove the `--ram` flag",
+            vector_table.location,
+            vtor
+        );
+    }
+    core.write_core_reg(CoreRegisterAddress(SP.0), vector_table.initial_sp)?;
+    core.write_core_reg(
+        CoreRegisterAddress(PC.0),
+        clear_thumb_bit(vector_table.reset),
+    )?;
+    Ok(())
+}
+
+/// Checks if HW breakpoints can be set at `address`
+///
+/// Revision 1 of the Flash Patch and Breakpoint unit, and the Breakpoint Unit of ARMv6-M, only
+/// compare addresses in the code region; their breakpoints never hit e.g. a program in RAM
+pub(crate) fn can_break_at(core: &mut Core, address: u32) -> anyhow::Result<bool> {
+    if address < CODE_REGION_END {
+        return Ok(true);
+    }
+
+    // NOTE the REV field is 0 for revision 1 and reserved (0) in `BP_CTRL`
+    let revision = core.read_word_32(FP_CTRL)? >> 28;
+    Ok(revision != 0)
+}
+
+/// Makes the core halt (or stop halting) when it enters the HardFault handler, without using a
+/// breakpoint
+pub(crate) fn catch_hard_faults(core: &mut Core, enable: bool) -> anyhow::Result<()> {
+    let demcr = core.read_word_32(DEMCR)?;
+    let demcr = if enable {
+        demcr | DEMCR_VC_HARDERR
+    } else {
+        demcr & !DEMCR_VC_HARDERR
+    };
+    core.write_word_32(DEMCR, demcr)?;
+    Ok(())
+}
+
+/// Checks if the AAPCS requires functions to preserve the value of `reg`
+pub(crate) fn is_callee_saved(reg: Register) -> bool {
+    matches!(reg.0, 4..=11 | 13)
+}
+
 /// Checks if PC is the HardFault handler
 // XXX may want to relax this to cover the whole PC range of the `HardFault` handler
 pub(crate) fn is_hard_fault(pc: u32, vector_table: &VectorTable) -> bool {
@@ -0,0 +1,378 @@
+//! Decoding of defmt frames for all supported versions of the defmt wire format
+//!
+//! Firmware that uses defmt 0.2 has the wire format version `0.2`, firmware that uses defmt 0.3
+//! or 1.x has version `3` or `4`. Each is decoded by a different release of `defmt-decoder`
+
+use std::{collections::BTreeMap, env, path::PathBuf};
+
+use anyhow::bail;
+use defmt_decoder::DecodeError;
+use defmt_decoder_v3::{Encoding, StreamDecoder};
+use defmt_parser::Level;
+use object::read::{File as ElfFile, Object as _, ObjectSymbol as _};
+
+/// The defmt wire format versions that can be decoded
+pub(crate) fn supported_versions() -> Vec<&'static str> {
+    let mut versions = vec![defmt_decoder::DEFMT_VERSION];
+    versions.extend_from_slice(defmt_decoder_v3::DEFMT_VERSIONS);
+    versions
+}
+
+pub(crate) enum Table {
+    V2(defmt_decoder::Table),
+    V3(defmt_decoder_v3::Table),
+}
+
+/// Location of a defmt log statement
+pub(crate) struct Location {
+    pub(crate) file: PathBuf,
+    pub(crate) line: u64,
+    pub(crate) module: String,
+}
+
+/// Locations of the log statements, by frame index
+pub(crate) type Locations = BTreeMap<u64, Location>;
+
+/// Parses the defmt table and its locations from the ELF file `elf`, if it uses defmt
+pub(crate) fn table(elf: &[u8]) -> anyhow::Result<(Option<Table>, Option<Locations>)> {
+    // * skip defmt version check, if `PROBE_RUN_IGNORE_VERSION` matches one of the options
+    let ignore_version = matches!(
+        env::var("PROBE_RUN_IGNORE_VERSION").as_deref(),
+        Ok("true") | Ok("1")
+    );
+    let table = match version(elf)?.as_deref() {
+        Some(version) if defmt_decoder_v3::DEFMT_VERSIONS.contains(&version) => {
+            defmt_decoder_v3::Table::parse(elf)?.map(Table::V3)
+        }
+        Some(version) if version != defmt_decoder::DEFMT_VERSION && !ignore_version => {
+            bail!(unsupported_version(version))
+        }
+        // NOTE `defmt_decoder` reports a missing version symbol or `.defmt` section
+        _ if ignore_version => defmt_decoder::Table::parse_ignore_version(elf)?.map(Table::V2),
+        _ => defmt_decoder::Table::parse(elf)?.map(Table::V2),
+    };
+
+    // Extract the `Locations` from the table, if there is a table
+    let mut locs = None;
+    if let Some(table) = table.as_ref() {
+        let tmp = table.locations(elf)?;
+
+        if !table.is_empty() && tmp.is_empty() {
+            log::warn!("insufficient DWARF info; compile your program with `debug = 2` to enable location info");
+        } else if table
+            .indices()
+            .iter()
+            .all(|idx| tmp.contains_key(&(*idx as u64)))
+        {
+            locs = Some(tmp);
+        } else {
+            log::warn!("(BUG) location info is incomplete; it will be omitted from the output");
+        }
+    }
+    Ok((table, locs))
+}
+
+impl Table {
+    fn is_empty(&self) -> bool {
+        match self {
+            Table::V2(table) => table.is_empty(),
+            Table::V3(table) => table.is_empty(),
+        }
+    }
+
+    fn indices(&self) -> Vec<usize> {
+        match self {
+            Table::V2(table) => table.indices().collect(),
+            Table::V3(table) => table.indices().collect(),
+        }
+    }
+
+    fn locations(&self, elf: &[u8]) -> anyhow::Result<Locations> {
+        Ok(match self {
+            Table::V2(table) => table
+                .get_locations(elf)?
+                .into_iter()
+                .map(|(index, loc)| (index, Location::new(loc.file, loc.line, loc.module)))
+                .collect(),
+            Table::V3(table) => table
+                .get_locations(elf)?
+                .into_iter()
+                .map(|(index, loc)| (index, Location::new(loc.file, loc.line, loc.module)))
+                .collect(),
+        })
+    }
+}
+
+impl Location {
+    fn new(file: PathBuf, line: u64, module: String) -> Self {
+        Self { file, line, module }
+    }
+}
+
+/// Reads the defmt wire format version from the ELF file `elf`
+fn version(elf: &[u8]) -> anyhow::Result<Option<String>> {
+    let elf = ElfFile::parse(elf)?;
+    let version = elf
+        .symbols()
+        .filter_map(|symbol| symbol.name().ok().and_then(version_of).map(str::to_string))
+        .next();
+    Ok(version)
+}
+
+/// Returns the version if `symbol` is the version symbol, e.g. `_defmt_version_ = 0.2`
+fn version_of(symbol: &str) -> Option<&str> {
+    // LLD keeps the quotes from the linker script
+    symbol
+        .trim_start_matches('"')
+        .strip_prefix("_defmt_version_ = ")
+        .map(|version| version.trim_end_matches('"'))
+}
+
+fn unsupported_version(version: &str) -> String {
+    let is_git_version = !version.contains('.') && version.parse::<u64>().is_err();
+    let suggestion = if version == "0.1" {
+        "`cargo install` `probe-run` 0.1, which supports defmt 0.1: `cargo install probe-run --version ~0.1`".to_string()
+    } else if is_git_version {
+        "migrate your firmware to a crates.io version of defmt (check https://defmt.ferrous-systems.com) OR `cargo install` a _git_ version of `probe-run` that supports it".to_string()
+    } else {
+        format!(
+            "`cargo install` a newer version of `probe-run` that supports defmt wire format version {}",
+            version
+        )
+    };
+    format!(
+        "defmt version mismatch: firmware is using {}, `probe-run` supports {}\nsuggestion: {}",
+        version,
+        supported_versions().join(", "),
+        suggestion
+    )
+}
+
+/// A decoded defmt frame
+pub(crate) struct Frame {
+    pub(crate) index: u64,
+    /// `None` for frames without a level, e.g. from `defmt::println!`
+    pub(crate) level: Option<Level>,
+    pub(crate) timestamp: Option<String>,
+    pub(crate) message: String,
+}
+
+impl Frame {
+    fn from_v2(frame: &defmt_decoder::Frame) -> Self {
+        Self {
+            index: frame.index(),
+            level: Some(frame.level()),
+            timestamp: frame.display_timestamp().map(|ts| ts.to_string()),
+            message: frame.display_message().to_string(),
+        }
+    }
+
+    fn from_v3(frame: &defmt_decoder_v3::Frame) -> Self {
+        Self {
+            index: frame.index(),
+            level: frame.level().map(|level| match level {
+                defmt_parser_v3::Level::Trace => Level::Trace,
+                defmt_parser_v3::Level::Debug => Level::Debug,
+                defmt_parser_v3::Level::Info => Level::Info,
+                defmt_parser_v3::Level::Warn => Level::Warn,
+                defmt_parser_v3::Level::Error => Level::Error,
+            }),
+            timestamp: frame.display_timestamp().map(|ts| ts.to_string()),
+            message: frame.display_message().to_string(),
+        }
+    }
+}
+
+pub(crate) enum Decoded {
+    Frame(Frame),
+    /// Data that could not be decoded and was skipped
+    Malformed(Vec<u8>),
+}
+
+/// Decodes the defmt frames received on one RTT channel
+pub(crate) struct Decoder<'t> {
+    table: &'t Table,
+    pending: Pending,
+    /// Set if the frames are rzCOBS encoded (defmt 0.3+)
+    rzcobs: Option<Box<dyn StreamDecoder + 't>>,
+}
+
+impl<'t> Decoder<'t> {
+    pub(crate) fn new(table: &'t Table) -> Self {
+        let rzcobs = match table {
+            Table::V3(table) if table.encoding() == Encoding::Rzcobs => {
+                Some(table.new_stream_decoder())
+            }
+            _ => None,
+        };
+        Self {
+            table,
+            pending: Pending::default(),
+            rzcobs,
+        }
+    }
+
+    pub(crate) fn received(&mut self, bytes: &[u8]) {
+        self.pending.extend(bytes);
+    }
+
+    /// Returns the next frame, or `None` if more data is needed
+    pub(crate) fn decode(&mut self) -> Option<Decoded> {
+        if let Some(rzcobs) = &mut self.rzcobs {
+            // frames are terminated by a zero byte, so malformed frames are easily skipped
+            let bytes = self.pending.as_slice();
+            let start = bytes
+                .iter()
+                .position(|byte| *byte != 0)
+                .unwrap_or(bytes.len());
+            self.pending.consume(start);
+            let end = self.pending.as_slice().iter().position(|byte| *byte == 0)? + 1;
+            let frame = self.pending.as_slice()[..end].to_vec();
+            self.pending.consume(end);
+
+            rzcobs.received(&frame);
+            return Some(match rzcobs.decode() {
+                Ok(decoded) => Decoded::Frame(Frame::from_v3(&decoded)),
+                Err(_) => Decoded::Malformed(frame),
+            });
+        }
+
+        let bytes = self.pending.as_slice();
+        let (decoded, consumed) = match decode_raw(self.table, bytes) {
+            Ok((frame, consumed)) => (Decoded::Frame(frame), consumed),
+            Err(DecodeError::UnexpectedEof) => return None,
+            Err(DecodeError::Malformed) => {
+                let malformed = malformed_len(self.table, bytes);
+                (Decoded::Malformed(bytes[..malformed].to_vec()), malformed)
+            }
+        };
+        self.pending.consume(consumed);
+        Some(decoded)
+    }
+}
+
+/// Data that has not been decoded yet
+///
+/// Decoded frames are only removed from the front of the buffer once they take up half of it, so
+/// that decoding a large backlog takes linear time
+#[derive(Default)]
+struct Pending {
+    bytes: Vec<u8>,
+    /// Start of the data that has not been decoded yet
+    start: usize,
+}
+
+impl Pending {
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.start != 0 && self.start >= self.bytes.len() / 2 {
+            self.bytes.drain(..self.start);
+            self.start = 0;
+        }
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[self.start..]
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+    }
+}
+
+/// Decodes a frame that is not rzCOBS encoded
+fn decode_raw(table: &Table, bytes: &[u8]) -> Result<(Frame, usize), DecodeError> {
+    match table {
+        Table::V2(table) => table
+            .decode(bytes)
+            .map(|(frame, consumed)| (Frame::from_v2(&frame), consumed)),
+        Table::V3(table) => table
+            .decode(bytes)
+            .map(|(frame, consumed)| (Frame::from_v3(&frame), consumed))
+            .map_err(|e| match e {
+                defmt_decoder_v3::DecodeError::UnexpectedEof => DecodeError::UnexpectedEof,
+                defmt_decoder_v3::DecodeError::Malformed => DecodeError::Malformed,
+            }),
+    }
+}
+
+/// Returns the number of bytes at the start of `bytes` to skip so that decoding can resume
+///
+/// Frames that are not rzCOBS encoded have no delimiters, so this skips to the first offset at
+/// which a frame can be decoded, or that could be the start of an incomplete frame
+fn malformed_len(table: &Table, bytes: &[u8]) -> usize {
+    (1..bytes.len())
+        .find(|&start| {
+            !matches!(
+                decode_raw(table, &bytes[start..]),
+                Err(DecodeError::Malformed)
+            )
+        })
+        .unwrap_or(bytes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use defmt_decoder::{StringEntry, TableEntry, Tag};
+    use rstest::rstest;
+
+    #[rstest]
+    // the index of the first frame doesn't exist
+    #[case(&[5, 0, 0], 1)]
+    #[case(&[5, 6, 1, 0], 2)]
+    // an incomplete frame may follow
+    #[case(&[5, 1], 1)]
+    #[case(&[5, 6, 7], 3)]
+    fn resynchronization(#[case] bytes: &[u8], #[case] expected: usize) {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            0,
+            TableEntry::new(
+                StringEntry::new(Tag::Info, "hello".to_string()),
+                "".to_string(),
+            ),
+        );
+        entries.insert(
+            1,
+            TableEntry::new(
+                StringEntry::new(Tag::Info, "{=u8}".to_string()),
+                "".to_string(),
+            ),
+        );
+        let table = Table::V2(defmt_decoder::Table::new(entries));
+        assert_eq!(expected, malformed_len(&table, bytes));
+    }
+
+    #[test]
+    fn pending() {
+        let mut pending = Pending::default();
+        pending.extend(&[1, 2, 3, 4]);
+        pending.consume(3);
+        assert_eq!([4], pending.as_slice());
+
+        // the consumed bytes are dropped once they take up half of the buffer
+        pending.extend(&[5, 6]);
+        assert_eq!([4, 5, 6], pending.as_slice());
+        assert_eq!(3, pending.bytes.len());
+    }
+
+    #[rstest]
+    #[case("_defmt_version_ = 0.2", Some("0.2"))]
+    #[case("\"_defmt_version_ = 3\"", Some("3"))]
+    #[case("_defmt_timestamp", None)]
+    fn version_symbol(#[case] symbol: &str, #[case] expected: Option<&str>) {
+        assert_eq!(expected, version_of(symbol));
+    }
+
+    #[rstest]
+    #[case("0.1", "probe-run --version ~0.1")]
+    #[case("5", "a newer version of `probe-run`")]
+    #[case("e739d0ac703dfa629a159be329e8c62a1c3ed206", "_git_ version")]
+    fn unsupported(#[case] version: &str, #[case] suggestion: &str) {
+        let message = unsupported_version(version);
+        assert!(message.contains(suggestion), "{}", message);
+        assert!(message.contains("supports 0.2, 3, 4"), "{}", message);
+    }
+}
@@ -0,0 +1,120 @@
+//! Host-side filtering of defmt log frames by level and module
+
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use defmt_parser::Level;
+use log::LevelFilter;
+
+/// `env_logger`-like filter, e.g. `warn,my_crate::radio=trace`
+///
+/// Each comma-separated directive is either a level, which applies to all modules, a module path,
+/// which enables all levels for that module, or `module=level`. The directive with the longest
+/// matching module path wins
+#[derive(Debug, PartialEq)]
+pub(crate) struct Filter {
+    default: LevelFilter,
+    /// Module paths and their maximum levels
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Checks whether a frame with `level` logged from `module` (if known) should be printed
+    pub(crate) fn enabled(&self, level: Level, module: Option<&str>) -> bool {
+        let max_level = module
+            .and_then(|module| {
+                self.modules
+                    .iter()
+                    .filter(|(path, _)| is_in_module(module, path))
+                    .max_by_key(|(path, _)| path.len())
+            })
+            .map(|(_, max_level)| *max_level)
+            .unwrap_or(self.default);
+        log_level(level) <= max_level
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter {
+            default: LevelFilter::Trace,
+            modules: vec![],
+        };
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let (name, level) = (parts.next().unwrap_or_default(), parts.next());
+            match level {
+                Some(level) => filter.modules.push((name.to_string(), parse_level(level)?)),
+                None => match name.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.modules.push((name.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+fn parse_level(s: &str) -> anyhow::Result<LevelFilter> {
+    s.parse().map_err(|_| {
+        anyhow!(
+            "invalid log level `{}` (expected `off`, `error`, `warn`, `info`, `debug` or `trace`)",
+            s
+        )
+    })
+}
+
+/// Checks whether `module` is `path` or one of its submodules
+fn is_in_module(module: &str, path: &str) -> bool {
+    match module.strip_prefix(path) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+pub(crate) fn log_level(level: Level) -> log::Level {
+    match level {
+        Level::Trace => log::Level::Trace,
+        Level::Debug => log::Level::Debug,
+        Level::Info => log::Level::Info,
+        Level::Warn => log::Level::Warn,
+        Level::Error => log::Level::Error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", Level::Trace, Some("app"), true)]
+    #[case("warn", Level::Info, Some("app"), false)]
+    #[case("warn", Level::Error, None, true)]
+    #[case("warn,app::radio=trace", Level::Trace, Some("app::radio"), true)]
+    #[case("warn,app::radio=trace", Level::Trace, Some("app::radio::tx"), true)]
+    #[case("warn,app::radio=trace", Level::Trace, Some("app::radios"), false)]
+    #[case("warn,app::radio=trace", Level::Info, None, false)]
+    #[case("off,app", Level::Debug, Some("app::usb"), true)]
+    #[case("app=info,app::usb=off", Level::Error, Some("app::usb"), false)]
+    #[case("app=info,app::usb=off", Level::Info, Some("app::main"), true)]
+    fn filtering(
+        #[case] filter: &str,
+        #[case] level: Level,
+        #[case] module: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        let filter = filter.parse::<Filter>().unwrap();
+        assert_eq!(expected, filter.enabled(level, module));
+    }
+
+    #[test]
+    fn invalid_level() {
+        assert!("app=loud".parse::<Filter>().is_err());
+    }
+}
@@ -18,7 +18,7 @@ impl<'p> Path<'p> {
         let mut components = path.components();
 
         let mut registry_prefix = PathBuf::new();
-        while let Some(component) = components.next() {
+        for component in components.by_ref() {
             registry_prefix.push(component.as_os_str());
 
             if let Component::Normal(component) = component {
@@ -49,6 +49,18 @@ impl<'p> Path<'p> {
         })
     }
 
+    /// Name of the crate without its version, e.g. `cortex-m-rt`
+    pub(crate) fn crate_name(&self) -> &'p str {
+        // crate names may contain dashes and digits (e.g. `sha-1`); the version is the first
+        // dash-separated suffix that looks like `major.minor.patch`
+        self.crate_name_version
+            .match_indices('-')
+            .map(|(index, _)| index)
+            .find(|index| is_version(&self.crate_name_version[index + 1..]))
+            .map(|index| &self.crate_name_version[..index])
+            .unwrap_or(self.crate_name_version)
+    }
+
     pub(crate) fn format_short(&self) -> String {
         format!(
             "[{}]{}{}",
@@ -69,10 +81,23 @@ impl<'p> Path<'p> {
     }
 }
 
+fn is_version(s: &str) -> bool {
+    let mut parts = s.splitn(3, '.');
+    let mut is_number = || {
+        parts
+            .next()
+            .map(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+            .unwrap_or(false)
+    };
+    is_number() && is_number() && parts.next().is_some()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use rstest::rstest;
+
     #[test]
     fn end_to_end() {
         let input = StdPath::new(
@@ -95,4 +120,19 @@ mod tests {
 
         assert_eq!(expected_str, formatted_str);
     }
+
+    #[rstest]
+    #[case("cortex-m-rt-0.6.13", "cortex-m-rt")]
+    #[case("sha-1-0.9.0", "sha-1")]
+    #[case("defmt-0.2.0-alpha.1", "defmt")]
+    #[case("no_version", "no_version")]
+    fn crate_names(#[case] crate_name_version: &str, #[case] expected: &str) {
+        let path = Path {
+            registry_prefix: PathBuf::new(),
+            crate_name_version,
+            path: StdPath::new("src/lib.rs"),
+        };
+
+        assert_eq!(expected, path.crate_name());
+    }
 }
@@ -33,6 +33,16 @@ impl<'p> Path<'p> {
         }
     }
 
+    /// Name of the crate the path points into, if known
+    pub(crate) fn crate_name(&self) -> Option<&'p str> {
+        match self {
+            Path::Cratesio(cratesio) => Some(cratesio.crate_name()),
+            Path::RustStd(rust_std) => rust_std.crate_name(),
+            Path::Rustc(rustc) => rustc.crate_name(),
+            Path::Verbatim(_) => None,
+        }
+    }
+
     pub(crate) fn format_short(&self) -> String {
         match self {
             Path::Cratesio(cratesio) => cratesio.format_short(),
@@ -52,7 +62,7 @@ impl<'p> Path<'p> {
     }
 }
 
-fn get_component_normal(component: Component) -> Option<&OsStr> {
+fn get_component_normal(component: Component<'_>) -> Option<&OsStr> {
     if let Component::Normal(string) = component {
         Some(string)
     } else {
@@ -25,7 +25,7 @@ impl<'p> Path<'p> {
         let mut components = path.components();
 
         let mut rustup_prefix = PathBuf::new();
-        while let Some(component) = components.next() {
+        for component in components.by_ref() {
             rustup_prefix.push(component);
 
             if let Component::Normal(component) = component {
@@ -39,7 +39,7 @@ impl<'p> Path<'p> {
             Toolchain::from_str(super::get_component_normal(components.next()?)?.to_str()?);
 
         let mut rust_std_prefix = PathBuf::new();
-        while let Some(component) = components.next() {
+        for component in components.by_ref() {
             rust_std_prefix.push(component);
 
             if let Component::Normal(component) = component {
@@ -59,6 +59,11 @@ impl<'p> Path<'p> {
         })
     }
 
+    /// Name of the standard library crate (e.g. `core`) the path points into
+    pub(crate) fn crate_name(&self) -> Option<&'p str> {
+        self.rust_repo_path.crate_name()
+    }
+
     pub(crate) fn format_short(&self) -> String {
         format!(
             "[{}]{}{}",
@@ -9,7 +9,7 @@ pub(crate) enum Toolchain<'p> {
 }
 
 impl<'p> Toolchain<'p> {
-    pub(crate) fn from_str(input: &str) -> Toolchain {
+    pub(crate) fn from_str(input: &str) -> Toolchain<'_> {
         if let Some(toolchain) = One52::from_str(input) {
             Toolchain::One52(toolchain)
         } else {
@@ -17,14 +17,14 @@ impl<'p> Toolchain<'p> {
         }
     }
 
-    pub(crate) fn format_highlight(&self) -> Cow<str> {
+    pub(crate) fn format_highlight(&self) -> Cow<'_, str> {
         match self {
             Toolchain::One52(toolchain) => toolchain.format_highlight().into(),
             Toolchain::Verbatim(toolchain) => Cow::Borrowed(toolchain),
         }
     }
 
-    pub(crate) fn format_short(&self) -> Cow<str> {
+    pub(crate) fn format_short(&self) -> Cow<'_, str> {
         match self {
             Toolchain::One52(toolchain) => toolchain.format_short(),
             Toolchain::Verbatim(toolchain) => Cow::Borrowed(toolchain),
@@ -97,7 +97,7 @@ impl<'p> One52<'p> {
         )
     }
 
-    fn format_short(&self) -> Cow<str> {
+    fn format_short(&self) -> Cow<'_, str> {
         match self.channel {
             Channel::Beta => "beta".into(),
             Channel::Nightly { date } => {
@@ -19,7 +19,7 @@ impl<'p> Path<'p> {
         let mut components = path.components();
 
         let mut rustc_prefix = PathBuf::new();
-        while let Some(component) = components.next() {
+        for component in components.by_ref() {
             rustc_prefix.push(component);
 
             if let Component::Normal(component) = component {
@@ -43,6 +43,16 @@ impl<'p> Path<'p> {
         })
     }
 
+    /// Path relative to the root of the rust-lang/rust repository
+    pub(crate) fn rust_repo_path(&self) -> String {
+        self.rust_repo_path.format()
+    }
+
+    /// Name of the standard library crate (e.g. `core`) the path points into
+    pub(crate) fn crate_name(&self) -> Option<&'p str> {
+        self.rust_repo_path.crate_name()
+    }
+
     pub(crate) fn format_short(&self) -> String {
         format!(
             "[rust]{}{}",
@@ -0,0 +1,206 @@
+//! Comparing the firmware on the device with the ELF file
+
+mod progress;
+
+use anyhow::anyhow;
+use object::{
+    elf::{FileHeader32, PT_LOAD},
+    read::elf::{FileHeader as _, ProgramHeader as _},
+    Bytes, Endianness,
+};
+use probe_rs::{Core, MemoryInterface as _};
+
+pub(crate) use progress::{Phase, Progress};
+
+/// Number of bytes read back and compared at a time
+const CHUNK_SIZE: usize = 1024;
+
+/// Loadable ELF segment, i.e. data that `probe-rs` writes to flash
+#[derive(Debug, PartialEq)]
+pub(crate) struct Segment<'elf> {
+    /// Load (physical) address, which differs from the run address for e.g. `.data`
+    pub(crate) address: u32,
+    /// Run (virtual) address
+    pub(crate) virtual_address: u32,
+    pub(crate) data: &'elf [u8],
+}
+
+/// Returns the non-empty loadable segments of the ELF file `bytes`
+pub(crate) fn segments(bytes: &[u8]) -> anyhow::Result<Vec<Segment<'_>>> {
+    let data = Bytes(bytes);
+    let header = FileHeader32::<Endianness>::parse(data)?;
+    let endian = header.endian()?;
+
+    let mut segments = vec![];
+    for program_header in header.program_headers(endian, data)? {
+        if program_header.p_type(endian) != PT_LOAD || program_header.p_filesz(endian) == 0 {
+            continue;
+        }
+
+        let data = program_header
+            .data(endian, data)
+            .map_err(|()| anyhow!("loadable segment is out of bounds"))?;
+        segments.push(Segment {
+            address: program_header.p_paddr(endian),
+            virtual_address: program_header.p_vaddr(endian),
+            data: data.0,
+        });
+    }
+    Ok(segments)
+}
+
+/// Returns the address at which the data that runs at `virtual_address` is loaded
+pub(crate) fn load_address(segments: &[Segment], virtual_address: u32) -> u32 {
+    segments
+        .iter()
+        .find(|segment| {
+            (segment.virtual_address..segment.virtual_address + segment.data.len() as u32)
+                .contains(&virtual_address)
+        })
+        .map(|segment| segment.address + (virtual_address - segment.virtual_address))
+        .unwrap_or(virtual_address)
+}
+
+/// Reads back the memory the `segments` would be written to and returns whether it already holds
+/// their contents
+pub(crate) fn is_up_to_date(core: &mut Core, segments: &[Segment]) -> anyhow::Result<bool> {
+    let mut is_up_to_date = true;
+    read_back(core, segments, |address, expected, actual| {
+        if actual == expected {
+            return true;
+        }
+
+        log::debug!(
+            "flash contents differ from the ELF file in 0x{:08X}..0x{:08X}",
+            address,
+            address + expected.len() as u32
+        );
+        is_up_to_date = false;
+        false
+    })?;
+    Ok(is_up_to_date)
+}
+
+/// Reads back all of the memory the `segments` were written to and reports how it differs from
+/// their contents
+pub(crate) fn verify(
+    core: &mut Core,
+    segments: &[Segment],
+    progress: &Progress,
+) -> anyhow::Result<Option<Mismatch>> {
+    let total = segments
+        .iter()
+        .map(|segment| segment.data.len() as u64)
+        .sum();
+    progress.start(Phase::Verify, total);
+
+    let mut mismatch = None;
+    read_back(core, segments, |address, expected, actual| {
+        Mismatch::record(&mut mismatch, address, expected, actual);
+        progress.advance(expected.len() as u64);
+        true
+    })?;
+    progress.finish();
+    Ok(mismatch)
+}
+
+/// Calls `f` with the address, expected and actual contents of each chunk of the `segments`
+/// until it returns `false`
+fn read_back(
+    core: &mut Core,
+    segments: &[Segment],
+    mut f: impl FnMut(u32, &[u8], &[u8]) -> bool,
+) -> anyhow::Result<()> {
+    let mut buffer = [0; CHUNK_SIZE];
+    for segment in segments {
+        let mut address = segment.address;
+        for expected in segment.data.chunks(CHUNK_SIZE) {
+            let actual = &mut buffer[..expected.len()];
+            core.read_8(address, actual)?;
+            if !f(address, expected, actual) {
+                return Ok(());
+            }
+            address += expected.len() as u32;
+        }
+    }
+    Ok(())
+}
+
+/// Differences between the flash contents and the ELF file
+#[derive(Debug, PartialEq)]
+pub(crate) struct Mismatch {
+    /// Address of the first byte that differs
+    pub(crate) address: u32,
+    pub(crate) expected: u8,
+    pub(crate) actual: u8,
+    /// Total number of bytes that differ
+    pub(crate) count: usize,
+}
+
+impl Mismatch {
+    /// Adds the differences between the `expected` and `actual` bytes at `address` to `mismatch`
+    fn record(mismatch: &mut Option<Self>, address: u32, expected: &[u8], actual: &[u8]) {
+        for (offset, (expected, actual)) in expected.iter().zip(actual).enumerate() {
+            if expected == actual {
+                continue;
+            }
+
+            match mismatch {
+                Some(mismatch) => mismatch.count += 1,
+                None => {
+                    *mismatch = Some(Mismatch {
+                        address: address + offset as u32,
+                        expected: *expected,
+                        actual: *actual,
+                        count: 1,
+                    })
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0x0000_0100, 0x0000_0100)]
+    #[case(0x2000_0004, 0x0000_1004)]
+    #[case(0x3000_0000, 0x3000_0000)]
+    fn load_addresses(#[case] virtual_address: u32, #[case] expected: u32) {
+        let data = [0; 16];
+        let segments = [
+            Segment {
+                address: 0x0000_0000,
+                virtual_address: 0x0000_0000,
+                data: &[0; 0x1000],
+            },
+            Segment {
+                address: 0x0000_1000,
+                virtual_address: 0x2000_0000,
+                data: &data,
+            },
+        ];
+        assert_eq!(expected, load_address(&segments, virtual_address));
+    }
+
+    #[test]
+    fn mismatches() {
+        let mut mismatch = None;
+        Mismatch::record(&mut mismatch, 0x1000, &[1, 2, 3, 4], &[1, 2, 3, 4]);
+        assert_eq!(None, mismatch);
+
+        Mismatch::record(&mut mismatch, 0x1004, &[1, 2, 3, 4], &[1, 0xff, 3, 0xff]);
+        Mismatch::record(&mut mismatch, 0x2000, &[1, 2], &[0xff, 2]);
+        let expected = Mismatch {
+            address: 0x1005,
+            expected: 2,
+            actual: 0xff,
+            count: 3,
+        };
+        assert_eq!(Some(expected), mismatch);
+    }
+}
@@ -0,0 +1,280 @@
+//! Progress output and timing of the flashing phases
+
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, IsTerminal as _, Write as _},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use probe_rs::flashing::{FlashProgress, ProgressEvent};
+
+/// Minimum time between two updates of the progress line
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Phase {
+    /// Reading back flash contents that must be preserved
+    Fill,
+    Erase,
+    Program,
+    Verify,
+}
+
+impl Phase {
+    fn verb(self) -> &'static str {
+        match self {
+            Phase::Fill => "reading",
+            Phase::Erase => "erasing",
+            Phase::Program => "programming",
+            Phase::Verify => "verifying",
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Fill => "fill",
+            Phase::Erase => "erase",
+            Phase::Program => "program",
+            Phase::Verify => "verify",
+        })
+    }
+}
+
+/// Time spent in each flashing phase
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Report {
+    pub(crate) phases: Vec<PhaseReport>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PhaseReport {
+    pub(crate) phase: Phase,
+    pub(crate) seconds: f64,
+}
+
+impl Report {
+    pub(crate) fn total_seconds(&self) -> f64 {
+        self.phases.iter().map(|phase| phase.seconds).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    /// e.g. `erase 0.52s, program 1.70s, verify 0.09s`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, phase) in self.phases.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} {:.2}s", phase.phase, phase.seconds)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the progress of the flashing phases and records how long they take
+#[derive(Clone)]
+pub(crate) struct Progress {
+    state: Rc<RefCell<State>>,
+}
+
+struct State {
+    /// Whether the progress line can be updated in place
+    interactive: bool,
+    /// Number of bytes to fill, erase and program, from the flash layout
+    totals: [u32; 3],
+    current: Option<Current>,
+    report: Report,
+}
+
+struct Current {
+    phase: Phase,
+    started: Instant,
+    last_update: Option<Instant>,
+    done: u64,
+    total: u64,
+}
+
+impl Progress {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(State {
+                interactive: io::stderr().is_terminal(),
+                totals: [0; 3],
+                current: None,
+                report: Report::default(),
+            })),
+        }
+    }
+
+    /// Returns a `probe-rs` progress handler that reports to `self`
+    pub(crate) fn flash_progress(&self) -> FlashProgress {
+        let progress = self.clone();
+        FlashProgress::new(move |event| progress.on_event(event))
+    }
+
+    fn on_event(&self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Initialized { flash_layout } => {
+                // NOTE `FlashLayout` itself is not exported by `probe-rs`
+                self.state.borrow_mut().totals = [
+                    flash_layout.fills().iter().map(|fill| fill.size()).sum(),
+                    flash_layout
+                        .sectors()
+                        .iter()
+                        .map(|sector| sector.size())
+                        .sum(),
+                    flash_layout.pages().iter().map(|page| page.size()).sum(),
+                ]
+            }
+            ProgressEvent::StartedFilling => self.start_layout_phase(Phase::Fill, 0),
+            ProgressEvent::StartedErasing => self.start_layout_phase(Phase::Erase, 1),
+            ProgressEvent::StartedProgramming => self.start_layout_phase(Phase::Program, 2),
+            ProgressEvent::PageFilled { size, .. }
+            | ProgressEvent::SectorErased { size, .. }
+            | ProgressEvent::PageProgrammed { size, .. } => self.advance(size.into()),
+            ProgressEvent::FinishedFilling
+            | ProgressEvent::FinishedErasing
+            | ProgressEvent::FinishedProgramming => self.finish(),
+            ProgressEvent::FailedFilling
+            | ProgressEvent::FailedErasing
+            | ProgressEvent::FailedProgramming => self.fail(),
+        }
+    }
+
+    fn start_layout_phase(&self, phase: Phase, index: usize) {
+        let total = self.state.borrow().totals[index];
+        self.start(phase, total.into());
+    }
+
+    pub(crate) fn start(&self, phase: Phase, total: u64) {
+        self.state.borrow_mut().current = Some(Current {
+            phase,
+            started: Instant::now(),
+            last_update: None,
+            done: 0,
+            total,
+        });
+        self.advance(0);
+    }
+
+    pub(crate) fn advance(&self, bytes: u64) {
+        let mut state = self.state.borrow_mut();
+        let interactive = state.interactive;
+        let current = match &mut state.current {
+            Some(current) => current,
+            None => return,
+        };
+        current.done += bytes;
+
+        let now = Instant::now();
+        let due = current
+            .last_update
+            .map(|last_update| now - last_update >= UPDATE_INTERVAL)
+            .unwrap_or(true);
+        if interactive && (due || current.done == current.total) {
+            current.last_update = Some(now);
+            let elapsed = now - current.started;
+            eprint!(
+                "\r\x1b[K  {} {} / {} ({:>3}%, {})",
+                current.phase.verb(),
+                kib(current.done),
+                kib(current.total),
+                percentage(current.done, current.total),
+                throughput(current.done, elapsed),
+            );
+            io::stderr().flush().ok();
+        }
+    }
+
+    pub(crate) fn finish(&self) {
+        let mut state = self.state.borrow_mut();
+        let current = match state.current.take() {
+            Some(current) => current,
+            None => return,
+        };
+        if state.interactive {
+            eprint!("\r\x1b[K");
+        }
+
+        let elapsed = current.started.elapsed();
+        log::info!(
+            "{} {} took {:.2}s ({})",
+            current.phase.verb(),
+            kib(current.done),
+            elapsed.as_secs_f64(),
+            throughput(current.done, elapsed)
+        );
+        state.report.phases.push(PhaseReport {
+            phase: current.phase,
+            seconds: elapsed.as_secs_f64(),
+        });
+    }
+
+    fn fail(&self) {
+        let mut state = self.state.borrow_mut();
+        if let Some(current) = state.current.take() {
+            if state.interactive {
+                eprint!("\r\x1b[K");
+            }
+            log::error!("{} failed", current.phase.verb());
+        }
+    }
+
+    /// Returns the time spent in the phases that have finished so far
+    pub(crate) fn report(&self) -> Report {
+        self.state.borrow().report.clone()
+    }
+}
+
+fn kib(bytes: u64) -> String {
+    format!("{:.2} KiB", bytes as f64 / 1024.0)
+}
+
+fn percentage(done: u64, total: u64) -> u64 {
+    (done * 100).checked_div(total).unwrap_or(100).min(100)
+}
+
+fn throughput(bytes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    if seconds == 0.0 {
+        return "- KiB/s".to_string();
+    }
+    format!("{:.1} KiB/s", bytes as f64 / 1024.0 / seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, 0, 100)]
+    #[case(512, 2048, 25)]
+    #[case(4096, 2048, 100)]
+    fn percentages(#[case] done: u64, #[case] total: u64, #[case] expected: u64) {
+        assert_eq!(expected, percentage(done, total));
+    }
+
+    #[test]
+    fn report() {
+        let report = Report {
+            phases: vec![
+                PhaseReport {
+                    phase: Phase::Erase,
+                    seconds: 0.5,
+                },
+                PhaseReport {
+                    phase: Phase::Program,
+                    seconds: 1.25,
+                },
+            ],
+        };
+        assert_eq!("erase 0.50s, program 1.25s", report.to_string());
+        assert_eq!(1.75, report.total_seconds());
+    }
+}
@@ -0,0 +1,175 @@
+//! Host-side timestamps for log lines
+
+use std::{
+    io::{self, Write},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use colored::Colorize as _;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What the timestamp shows
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Mode {
+    /// Time since the device was reset
+    #[default]
+    Elapsed,
+    /// Time of day (UTC)
+    Wall,
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "elapsed" => Ok(Mode::Elapsed),
+            "wall" => Ok(Mode::Wall),
+            _ => Err(anyhow!(
+                "invalid host timestamp mode (expected `elapsed` or `wall`)"
+            )),
+        }
+    }
+}
+
+/// Prefixes log lines with the time at which the host received them and the time since the
+/// previous line
+pub(crate) struct Clock {
+    mode: Mode,
+    /// When the device was reset
+    reset: SystemTime,
+    /// When the previous line was received, relative to `reset`
+    previous: Duration,
+    /// Whether the next raw byte starts a new line
+    at_line_start: bool,
+}
+
+impl Clock {
+    pub(crate) fn new(mode: Mode, reset: SystemTime) -> Self {
+        Self {
+            mode,
+            reset,
+            previous: Duration::from_secs(0),
+            at_line_start: true,
+        }
+    }
+
+    /// Returns the prefix for a line received `elapsed` time after the reset, e.g.
+    /// `1.234567 (+0.001000) `
+    pub(crate) fn prefix(&mut self, elapsed: Duration) -> String {
+        let delta = elapsed.saturating_sub(self.previous);
+        self.previous = elapsed;
+
+        let time = match self.mode {
+            Mode::Elapsed => format_elapsed(elapsed),
+            Mode::Wall => format_time_of_day(
+                (self.reset + elapsed)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ),
+        };
+        format!("{} ", format_prefix(&time, delta).dimmed())
+    }
+
+    /// Writes raw channel output received `elapsed` time after the reset, prefixing each line
+    pub(crate) fn write_text(
+        &mut self,
+        sink: &mut impl Write,
+        mut text: &[u8],
+        elapsed: Duration,
+    ) -> io::Result<()> {
+        while !text.is_empty() {
+            if self.at_line_start {
+                sink.write_all(self.prefix(elapsed).as_bytes())?;
+            }
+
+            let line_len = match text.iter().position(|byte| *byte == b'\n') {
+                Some(newline) => newline + 1,
+                None => text.len(),
+            };
+            let (line, rest) = text.split_at(line_len);
+            sink.write_all(line)?;
+            self.at_line_start = line.ends_with(b"\n");
+            text = rest;
+        }
+        Ok(())
+    }
+}
+
+fn format_prefix(time: &str, delta: Duration) -> String {
+    format!("{} (+{})", time, format_elapsed(delta))
+}
+
+/// `<seconds>.<microseconds>`
+fn format_elapsed(elapsed: Duration) -> String {
+    format!("{}.{:06}", elapsed.as_secs(), elapsed.subsec_micros())
+}
+
+/// `HH:MM:SS.mmm` of the given time since the UNIX epoch
+fn format_time_of_day(since_epoch: Duration) -> String {
+    let seconds = since_epoch.as_secs() % SECONDS_PER_DAY;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Duration::from_micros(0), "0.000000")]
+    #[case(Duration::from_micros(12_345_678), "12.345678")]
+    fn elapsed(#[case] duration: Duration, #[case] expected: &str) {
+        assert_eq!(expected, format_elapsed(duration));
+    }
+
+    #[test]
+    fn time_of_day() {
+        // 2021-05-21T13:04:05.678Z
+        let since_epoch = Duration::from_millis(1_621_602_245_678);
+        assert_eq!("13:04:05.678", format_time_of_day(since_epoch));
+    }
+
+    #[test]
+    fn prefix() {
+        assert_eq!(
+            "1.500000 (+0.000250)",
+            format_prefix("1.500000", Duration::from_micros(250))
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let mut clock = Clock::new(Mode::Elapsed, SystemTime::now());
+        let mut output = vec![];
+        clock
+            .write_text(&mut output, b"hello\nwor", Duration::from_millis(1))
+            .unwrap();
+        clock
+            .write_text(&mut output, b"ld\n\nbye", Duration::from_millis(3))
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines = output.split('\n').collect::<Vec<_>>();
+        assert_eq!(4, lines.len());
+        for (line, text) in lines.iter().zip(&["hello", "world", "", "bye"]) {
+            let prefix = line.strip_suffix(text).unwrap();
+            assert!(
+                prefix.contains(" (+") && prefix.ends_with(' '),
+                "{:?}",
+                line
+            );
+        }
+        assert!(lines[3].contains("0.003000 (+0.000000)"), "{:?}", lines[3]);
+    }
+}
@@ -0,0 +1,96 @@
+//! Terminal hyperlinks (OSC 8) to source code locations
+
+use std::path::Path;
+
+/// Link target used when `--hyperlinks` is passed without a template
+const DEFAULT_TEMPLATE: &str = "file://{path}";
+
+/// Editor URL template, e.g. `vscode://file{path}:{line}:{col}`
+///
+/// `{path}` is replaced with the absolute path of the source file, `{line}` and `{col}` with the
+/// location within the file
+#[derive(Debug)]
+pub(crate) struct Template(String);
+
+impl Template {
+    /// An empty `template` selects the `DEFAULT_TEMPLATE`
+    pub(crate) fn new(template: String) -> Self {
+        if template.is_empty() {
+            Self(DEFAULT_TEMPLATE.to_string())
+        } else {
+            Self(template)
+        }
+    }
+
+    /// Wraps `text` in a hyperlink to `path:line:column`
+    pub(crate) fn link(&self, text: &str, path: &Path, line: u32, column: Option<u32>) -> String {
+        format!(
+            "\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\",
+            self.url(path, line, column),
+            text
+        )
+    }
+
+    fn url(&self, path: &Path, line: u32, column: Option<u32>) -> String {
+        self.0
+            .replace("{path}", &percent_encode(&path.to_string_lossy()))
+            .replace("{line}", &line.to_string())
+            .replace("{col}", &column.unwrap_or(1).to_string())
+    }
+}
+
+/// Encodes all characters of `path` that are not allowed in a URL
+fn percent_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        DEFAULT_TEMPLATE,
+        "/home/user/app/src/main.rs",
+        Some(5),
+        "file:///home/user/app/src/main.rs"
+    )]
+    #[case(
+        "",
+        "/home/user/app/src/main.rs",
+        None,
+        "file:///home/user/app/src/main.rs"
+    )]
+    #[case(
+        "vscode://file{path}:{line}:{col}",
+        "/home/user/app/src/main.rs",
+        Some(5),
+        "vscode://file/home/user/app/src/main.rs:12:5"
+    )]
+    #[case(
+        "vscode://file{path}:{line}:{col}",
+        "/home/user/my app/main.rs",
+        None,
+        "vscode://file/home/user/my%20app/main.rs:12:1"
+    )]
+    fn urls(
+        #[case] template: &str,
+        #[case] path: &str,
+        #[case] column: Option<u32>,
+        #[case] expected: &str,
+    ) {
+        let template = Template::new(template.to_string());
+        assert_eq!(expected, template.url(Path::new(path), 12, column));
+    }
+}
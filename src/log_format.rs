@@ -0,0 +1,250 @@
+//! User-defined format of the log lines printed by `probe-run`
+
+use std::{fmt::Write as _, str::FromStr};
+
+use anyhow::{anyhow, bail};
+
+/// How each log line is rendered
+#[derive(Debug, PartialEq)]
+pub(crate) enum LogFormat {
+    /// e.g. `{timestamp} {level} {message} ({file}:{line})`
+    Template(Vec<Piece>),
+    /// `key=value` pairs, see <https://brandur.org/logfmt>
+    Logfmt,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Piece {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Field {
+    Timestamp,
+    Level,
+    Message,
+    File,
+    Line,
+    Module,
+    Channel,
+    Core,
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "timestamp" => Field::Timestamp,
+            "level" => Field::Level,
+            "message" => Field::Message,
+            "file" => Field::File,
+            "line" => Field::Line,
+            "module" => Field::Module,
+            "channel" => Field::Channel,
+            "core" => Field::Core,
+            _ => bail!(
+                "unknown placeholder `{{{}}}` (expected one of `{{timestamp}}`, `{{level}}`, `{{message}}`, `{{file}}`, `{{line}}`, `{{module}}`, `{{channel}}` or `{{core}}`)",
+                s
+            ),
+        })
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "logfmt" {
+            return Ok(LogFormat::Logfmt);
+        }
+
+        let mut pieces = vec![];
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or_else(|| {
+                        anyhow!("unclosed `{{` in log format (use `{{{{` for a literal `{{`)")
+                    })?;
+                    let field = rest[..end].parse()?;
+                    chars = rest[end + 1..].chars();
+
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Field(field));
+                }
+                '}' => bail!("unmatched `}}` in log format (use `}}}}` for a literal `}}`)"),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+
+        Ok(LogFormat::Template(pieces))
+    }
+}
+
+/// The values a log line is rendered from; fields that are not known are `None`
+#[derive(Debug, Default)]
+pub(crate) struct Line<'a> {
+    /// Device-side timestamp of a defmt frame
+    pub(crate) timestamp: Option<&'a str>,
+    pub(crate) level: Option<log::Level>,
+    pub(crate) message: &'a str,
+    pub(crate) file: Option<&'a str>,
+    pub(crate) line: Option<u32>,
+    pub(crate) module: Option<&'a str>,
+    /// Name of the RTT channel, or its index if it has no name
+    pub(crate) channel: &'a str,
+    pub(crate) core: usize,
+}
+
+impl LogFormat {
+    /// Renders `line` without a trailing newline
+    pub(crate) fn render(&self, line: &Line) -> String {
+        let mut output = String::new();
+        match self {
+            LogFormat::Template(pieces) => {
+                for piece in pieces {
+                    match piece {
+                        Piece::Literal(literal) => output.push_str(literal),
+                        Piece::Field(field) => {
+                            if let Some(value) = value(line, *field) {
+                                output.push_str(&value);
+                            }
+                        }
+                    }
+                }
+            }
+            LogFormat::Logfmt => {
+                let keys = [
+                    ("ts", Field::Timestamp),
+                    ("level", Field::Level),
+                    ("msg", Field::Message),
+                    ("file", Field::File),
+                    ("line", Field::Line),
+                    ("module", Field::Module),
+                    ("channel", Field::Channel),
+                    ("core", Field::Core),
+                ];
+                for (key, field) in keys.iter() {
+                    let value = match (field, value(line, *field)) {
+                        (Field::Level, Some(level)) => level.to_lowercase(),
+                        (_, Some(value)) => value,
+                        (_, None) => continue,
+                    };
+                    if !output.is_empty() {
+                        output.push(' ');
+                    }
+                    let _ = write!(output, "{}={}", key, logfmt_value(&value));
+                }
+            }
+        }
+        output
+    }
+}
+
+fn value(line: &Line, field: Field) -> Option<String> {
+    match field {
+        Field::Timestamp => line.timestamp.map(str::to_string),
+        Field::Level => line.level.map(|level| level.to_string()),
+        Field::Message => Some(line.message.to_string()),
+        Field::File => line.file.map(str::to_string),
+        Field::Line => line.line.map(|line| line.to_string()),
+        Field::Module => line.module.map(str::to_string),
+        Field::Channel => Some(line.channel.to_string()),
+        Field::Core => Some(line.core.to_string()),
+    }
+}
+
+/// Quotes and escapes `value` if needed
+fn logfmt_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c == ' ' || c == '=' || c == '"' || c.is_control());
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    fn line() -> Line<'static> {
+        Line {
+            timestamp: Some("1.500"),
+            level: Some(log::Level::Info),
+            message: "hello \"world\"",
+            file: Some("src/main.rs"),
+            line: Some(12),
+            module: Some("app"),
+            channel: "defmt",
+            core: 0,
+        }
+    }
+
+    #[rstest]
+    #[case("{level} {message}", "INFO hello \"world\"")]
+    #[case(
+        "{timestamp} [{channel}@{core}] {message} ({file}:{line} in {module})",
+        "1.500 [defmt@0] hello \"world\" (src/main.rs:12 in app)"
+    )]
+    #[case("{{{level}}}", "{INFO}")]
+    #[case("logfmt", "ts=1.500 level=info msg=\"hello \\\"world\\\"\" file=src/main.rs line=12 module=app channel=defmt core=0")]
+    fn rendering(#[case] format: &str, #[case] expected: &str) {
+        let format = format.parse::<LogFormat>().unwrap();
+        assert_eq!(expected, format.render(&line()));
+    }
+
+    #[test]
+    fn missing_fields() {
+        let line = Line {
+            message: "raw",
+            channel: "1",
+            ..Line::default()
+        };
+        let template = "{level}|{message}|{file}".parse::<LogFormat>().unwrap();
+        assert_eq!("|raw|", template.render(&line));
+        assert_eq!("msg=raw channel=1 core=0", LogFormat::Logfmt.render(&line));
+    }
+
+    #[rstest]
+    #[case("{lvl}")]
+    #[case("{level")]
+    #[case("level}")]
+    fn invalid(#[case] format: &str) {
+        assert!(format.parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,362 @@
+//! Printing the output of RTT channels, either raw or decoded as defmt frames
+
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    decoder::{Decoded, Decoder, Frame, Locations, Table},
+    defmt_filter, dep, host_timestamp, hyperlink,
+    log_format::{self, LogFormat},
+};
+
+/// Number of bytes of malformed defmt data that are shown in warnings
+const MALFORMED_BYTES_SHOWN: usize = 16;
+
+/// How the output of all channels is printed
+pub(crate) struct Settings<'a> {
+    /// `None` if the program doesn't use defmt
+    pub(crate) table: Option<&'a Table>,
+    pub(crate) locs: Option<&'a Locations>,
+    pub(crate) current_dir: &'a Path,
+    pub(crate) shorten_paths: bool,
+    pub(crate) hyperlinks: Option<&'a hyperlink::Template>,
+    pub(crate) defmt_filter: Option<&'a defmt_filter::Filter>,
+    pub(crate) host_timestamps: Option<host_timestamp::Mode>,
+    /// `None` prints defmt frames with the default logger and raw output verbatim
+    pub(crate) log_format: Option<&'a LogFormat>,
+}
+
+/// Malformed defmt data that could not be decoded
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct DecodeErrors {
+    /// Number of times the decoder had to resynchronize
+    pub(crate) count: usize,
+    /// Number of bytes that were skipped
+    pub(crate) bytes: usize,
+}
+
+impl DecodeErrors {
+    /// Warns about the malformed data, if there was any
+    pub(crate) fn report(&self) {
+        if self.count != 0 {
+            log::warn!(
+                "{} bytes of malformed defmt data were skipped in {} places; some logs are missing",
+                self.bytes,
+                self.count
+            );
+        }
+    }
+}
+
+/// Prints the output of one RTT channel
+pub(crate) struct Channel<'a> {
+    settings: &'a Settings<'a>,
+    /// Name of the RTT channel, or its index if it has no name
+    name: String,
+    /// Set if the channel carries defmt frames
+    decoder: Option<Decoder<'a>>,
+    host_clock: Option<host_timestamp::Clock>,
+    decode_errors: DecodeErrors,
+    /// Raw output that has not been printed yet because its line is incomplete; only used with
+    /// a log format
+    text: Vec<u8>,
+}
+
+impl<'a> Channel<'a> {
+    /// `reset` is the time at which the device was reset
+    pub(crate) fn new(
+        settings: &'a Settings<'a>,
+        name: String,
+        defmt: bool,
+        reset: SystemTime,
+    ) -> Self {
+        Self {
+            settings,
+            name,
+            decoder: settings.table.filter(|_| defmt).map(Decoder::new),
+            host_clock: settings
+                .host_timestamps
+                .map(|mode| host_timestamp::Clock::new(mode, reset)),
+            decode_errors: DecodeErrors::default(),
+            text: vec![],
+        }
+    }
+
+    /// Prints the `bytes` that were received `elapsed` time after the device was reset
+    pub(crate) fn print(
+        &mut self,
+        bytes: &[u8],
+        elapsed: Duration,
+        stdout: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        let decoder = match &mut self.decoder {
+            Some(decoder) => decoder,
+            None => return self.print_raw(bytes, elapsed, stdout),
+        };
+
+        decoder.received(bytes);
+        while let Some(decoded) = self.decoder.as_mut().and_then(Decoder::decode) {
+            match decoded {
+                Decoded::Frame(frame) => self.print_frame(&frame, elapsed, stdout)?,
+                Decoded::Malformed(malformed) => {
+                    // e.g. a firmware bug corrupted the data; skip to the next frame that can be
+                    // decoded instead of giving up on the logs
+                    let shown = malformed.len().min(MALFORMED_BYTES_SHOWN);
+                    log::warn!(
+                        "skipping {} bytes of malformed defmt data: {:02x?}{}",
+                        malformed.len(),
+                        &malformed[..shown],
+                        if shown < malformed.len() { " .." } else { "" }
+                    );
+                    log::trace!("malformed defmt data: {:02x?}", malformed);
+                    self.decode_errors.count += 1;
+                    self.decode_errors.bytes += malformed.len();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn print_raw(
+        &mut self,
+        bytes: &[u8],
+        elapsed: Duration,
+        stdout: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        if self.settings.log_format.is_some() {
+            return self.print_text(bytes, elapsed, stdout);
+        }
+
+        match &mut self.host_clock {
+            Some(host_clock) => host_clock.write_text(stdout, bytes, elapsed)?,
+            None => stdout.write_all(bytes)?,
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Prints the raw output whose line is still incomplete, e.g. because the device halted in the
+    /// middle of it; call once no more output will be received
+    pub(crate) fn finish(
+        &mut self,
+        elapsed: Duration,
+        stdout: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        if self.text.is_empty() {
+            return Ok(());
+        }
+
+        let text = std::mem::take(&mut self.text);
+        let message = String::from_utf8_lossy(&text);
+        let rendered = self.render(&log_format::Line {
+            message: message.trim_end_matches('\r'),
+            ..self.line()
+        });
+        self.write_line(&rendered, elapsed, stdout)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Returns the malformed defmt data that has been skipped so far
+    pub(crate) fn decode_errors(&self) -> DecodeErrors {
+        self.decode_errors
+    }
+
+    /// Prints the complete lines of raw output with the log format
+    fn print_text(
+        &mut self,
+        bytes: &[u8],
+        elapsed: Duration,
+        stdout: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        self.text.extend_from_slice(bytes);
+        let text = std::mem::take(&mut self.text);
+        let mut start = 0;
+        while let Some(newline) = text[start..].iter().position(|byte| *byte == b'\n') {
+            let line = &text[start..start + newline];
+            start += newline + 1;
+            let message = String::from_utf8_lossy(line);
+            let message = message.trim_end_matches('\r');
+            let rendered = self.render(&log_format::Line {
+                message,
+                ..self.line()
+            });
+            self.write_line(&rendered, elapsed, stdout)?;
+        }
+        // keep the incomplete last line
+        self.text = text[start..].to_vec();
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// The fields of a log line that are the same for all lines of this channel
+    fn line(&self) -> log_format::Line<'_> {
+        log_format::Line {
+            channel: &self.name,
+            ..log_format::Line::default()
+        }
+    }
+
+    fn render(&self, line: &log_format::Line) -> String {
+        // NOTE only called if there's a log format
+        self.settings.log_format.unwrap().render(line)
+    }
+
+    /// Writes a `rendered` line received `elapsed` time after the reset
+    fn write_line(
+        &mut self,
+        rendered: &str,
+        elapsed: Duration,
+        stdout: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        if let Some(host_clock) = &mut self.host_clock {
+            stdout.write_all(host_clock.prefix(elapsed).as_bytes())?;
+        }
+        writeln!(stdout, "{}", rendered)?;
+        Ok(())
+    }
+
+    fn print_frame(
+        &mut self,
+        frame: &Frame,
+        elapsed: Duration,
+        stdout: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        let settings = self.settings;
+        // NOTE(`[]` indexing) all indices in `table` have already been
+        // verified to exist in the `locs` map
+        let loc = settings.locs.map(|locs| &locs[&frame.index]);
+
+        let enabled = match (settings.defmt_filter, frame.level) {
+            (Some(filter), Some(level)) => filter.enabled(level, loc.map(|loc| &*loc.module)),
+            // frames without a level are always printed
+            _ => true,
+        };
+        if !enabled {
+            return Ok(());
+        }
+
+        let (mut file, mut line, mut mod_path) = (None, None, None);
+        if let Some(loc) = loc {
+            let mut path = if let Ok(relpath) = loc.file.strip_prefix(settings.current_dir) {
+                relpath.display().to_string()
+            } else {
+                let dep_path = dep::Path::from_std_path(&loc.file);
+
+                if settings.shorten_paths {
+                    dep_path.format_short()
+                } else if settings.log_format.is_some() {
+                    // NOTE no colors in user-defined formats
+                    loc.file.display().to_string()
+                } else {
+                    dep_path.format_highlight()
+                }
+            };
+
+            // NOTE escape sequences would end up inside the values of user-defined formats
+            if let (Some(hyperlinks), None) = (settings.hyperlinks, settings.log_format) {
+                path = hyperlinks.link(
+                    &path,
+                    &settings.current_dir.join(&loc.file),
+                    loc.line as u32,
+                    None,
+                );
+            }
+
+            file = Some(path);
+            line = Some(loc.line as u32);
+            mod_path = Some(loc.module.clone());
+        }
+
+        if settings.log_format.is_some() {
+            let rendered = self.render(&log_format::Line {
+                timestamp: frame.timestamp.as_deref(),
+                level: frame.level.map(defmt_filter::log_level),
+                message: &frame.message,
+                file: file.as_deref(),
+                line,
+                module: mod_path.as_deref(),
+                ..self.line()
+            });
+            self.write_line(&rendered, elapsed, stdout)?;
+            stdout.flush()?;
+            return Ok(());
+        }
+
+        if let Some(host_clock) = &mut self.host_clock {
+            // NOTE the logger prints defmt frames to stdout as well
+            stdout.write_all(host_clock.prefix(elapsed).as_bytes())?;
+        }
+        match frame.level {
+            // Forward the defmt frame to our logger.
+            Some(level) => log_defmt(frame, level, file.as_deref(), line, mod_path.as_deref()),
+            None => {
+                match &frame.timestamp {
+                    Some(timestamp) => writeln!(stdout, "{} {}", timestamp, frame.message)?,
+                    None => writeln!(stdout, "{}", frame.message)?,
+                }
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Like `defmt_decoder::log::log_defmt`, which only accepts frames of defmt 0.2
+fn log_defmt(
+    frame: &Frame,
+    level: defmt_parser::Level,
+    file: Option<&str>,
+    line: Option<u32>,
+    module_path: Option<&str>,
+) {
+    // NOTE `defmt_decoder::log` recognizes defmt frames by this target and takes the timestamp
+    // from it
+    let target = format!("defmt@{}", frame.timestamp.as_deref().unwrap_or_default());
+    log::logger().log(
+        &log::Record::builder()
+            .args(format_args!("{}", frame.message))
+            .level(defmt_filter::log_level(level))
+            .target(&target)
+            .module_path(module_path)
+            .file(file)
+            .line(line)
+            .build(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_line() {
+        let format = "{channel}: {message}".parse::<LogFormat>().unwrap();
+        let settings = Settings {
+            table: None,
+            locs: None,
+            current_dir: Path::new("/"),
+            shorten_paths: false,
+            hyperlinks: None,
+            defmt_filter: None,
+            host_timestamps: None,
+            log_format: Some(&format),
+        };
+        let mut channel = Channel::new(&settings, "0".to_string(), false, SystemTime::now());
+
+        let mut stdout = vec![];
+        channel
+            .print(b"first\r\nsec", Duration::default(), &mut stdout)
+            .unwrap();
+        channel
+            .print(b"ond", Duration::default(), &mut stdout)
+            .unwrap();
+        assert_eq!("0: first\n", String::from_utf8_lossy(&stdout));
+
+        channel.finish(Duration::default(), &mut stdout).unwrap();
+        assert_eq!("0: first\n0: second\n", String::from_utf8_lossy(&stdout));
+    }
+}
@@ -1,42 +1,68 @@
+mod arch;
 mod backtrace;
+mod chips;
+mod config;
 mod cortexm;
+mod decoder;
+mod defmt_filter;
 mod dep;
+mod flash;
+mod host_timestamp;
+mod hyperlink;
+mod log_format;
+mod logs;
+mod probes;
+mod recording;
 mod registers;
+mod riscv;
+mod rtt;
 mod stacked;
 
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     convert::TryInto,
-    env, fs,
-    io::{self, Write as _},
-    path::PathBuf,
+    env,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, IsTerminal as _},
+    path::{Path, PathBuf},
     process,
     str::FromStr,
     sync::atomic::{AtomicBool, Ordering},
     sync::{Arc, Mutex},
-    time::Duration,
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Context as _};
+use arch::Arch;
 use arrayref::array_ref;
 use colored::Colorize as _;
-use defmt_decoder::DEFMT_VERSION;
+use config::Config;
 use log::Level;
+use log_format::LogFormat;
 use object::{
     read::{File as ElfFile, Object as _, ObjectSection as _},
     ObjectSegment, ObjectSymbol, SymbolSection,
 };
 use probe_rs::{
-    config::{registry, MemoryRegion},
-    flashing::{self, Format},
-    DebugProbeInfo, MemoryInterface, Probe, Session,
+    config::{registry, MemoryRegion, RegistryError},
+    flashing::{self, DownloadOptions, Format},
+    MemoryInterface, Probe, Session,
 };
 use probe_rs_rtt::{Rtt, ScanRegion, UpChannel};
+use probes::ProbeFilter;
+use recording::{Record, Recorder, Recording};
 use signal_hook::consts::signal;
 use structopt::{clap::AppSettings, StructOpt};
 
 /// Successfull termination of process.
 const EXIT_SUCCESS: i32 = 0;
+const DEFAULT_MAX_BACKTRACE_LEN: u32 = 50;
+const MAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const RTT_ATTACH_RETRIES: usize = 10; // picked at random, increase if necessary
+/// Time between attempts to attach to RTT while the program runs
+const RTT_POLL_INTERVAL: Duration = Duration::from_millis(10);
 const STACK_CANARY: u8 = 0xAA;
 const SIGABRT: i32 = 134;
 const TIMEOUT: Duration = Duration::from_secs(1);
@@ -45,19 +71,25 @@ const TIMEOUT: Duration = Duration::from_secs(1);
 #[derive(StructOpt)]
 #[structopt(name = "probe-run", setting = AppSettings::TrailingVarArg)]
 struct Opts {
-    /// List supported chips and exit.
-    #[structopt(long)]
-    list_chips: bool,
+    /// List supported chips and exit; optionally only the chips whose name contains the given
+    /// pattern (case-insensitive)
+    #[structopt(long, value_name = "pattern")]
+    list_chips: Option<Option<String>>,
 
     /// Lists all the connected probes and exit.
     #[structopt(long)]
     list_probes: bool,
 
     /// The chip to program.
-    #[structopt(long, required_unless_one(&["list-chips", "list-probes", "version"]), env = "PROBE_RUN_CHIP")]
+    #[structopt(long, env = "PROBE_RUN_CHIP")]
     chip: Option<String>,
 
-    /// The probe to use (eg. `VID:PID`, `VID:PID:Serial`, or just `Serial`).
+    /// Path to a probe-rs target description (YAML) with additional chips
+    #[structopt(long, parse(from_os_str), env = "PROBE_RUN_CHIP_DESCRIPTION_PATH")]
+    chip_description_path: Option<PathBuf>,
+
+    /// The probe to use (eg. `VID:PID`, `VID:PID:Serial`, just `Serial`, the index shown by
+    /// `--list-probes`, a probe type such as `stlink`, or an alias from the configuration).
     #[structopt(long, env = "PROBE_RUN_PROBE")]
     probe: Option<String>,
 
@@ -70,13 +102,42 @@ struct Opts {
     elf: Option<PathBuf>,
 
     /// Skip writing the application binary to flash.
-    #[structopt(long, conflicts_with = "defmt")]
+    #[structopt(long)]
     no_flash: bool,
 
+    /// Flash the program without first checking whether the device already holds it
+    #[structopt(long, conflicts_with_all = &["no-flash", "ram", "attach"])]
+    force_flash: bool,
+
+    /// Load the program into RAM and run it from there instead of flashing it; the program must be
+    /// linked for RAM
+    #[structopt(long, conflicts_with_all = &["no-flash", "verify"])]
+    ram: bool,
+
+    /// Read back the flash after programming it and exit with an error if it differs from the ELF
+    /// file
+    #[structopt(long)]
+    verify: bool,
+
     /// Connect to device when NRST is pressed.
     #[structopt(long)]
     connect_under_reset: bool,
 
+    /// Connect to the program that is already running on the device instead of flashing and
+    /// resetting it
+    #[structopt(long, conflicts_with_all = &["ram", "verify", "connect-under-reset"])]
+    attach: bool,
+
+    /// With `--attach`, let the program continue after printing the backtrace instead of leaving
+    /// it halted
+    #[structopt(long, requires = "attach")]
+    resume: bool,
+
+    /// What the program does when an RTT buffer is full: `block-if-full` (default),
+    /// `no-block-skip` or `no-block-trim`; `<index>=<mode>` sets the mode of another up channel
+    #[structopt(long, env = "PROBE_RUN_RTT_MODE")]
+    rtt_mode: Option<rtt::Modes>,
+
     /// Enable more verbose logging.
     #[structopt(short, long, parse(from_occurrences))]
     verbose: u32,
@@ -89,27 +150,201 @@ struct Opts {
     #[structopt(long)]
     force_backtrace: bool,
 
-    /// Configure the number of lines to print before a backtrace gets cut off
-    #[structopt(long, default_value = "50")]
-    max_backtrace_len: u32,
+    /// Configure the number of lines to print before a backtrace gets cut off [default: 50]
+    #[structopt(long)]
+    max_backtrace_len: Option<u32>,
 
-    /// Whether to shorten paths (e.g. to crates.io dependencies) in backtraces and defmt logs
+    /// Scan the stack for probable return addresses when unwinding fails
     #[structopt(long)]
-    shorten_paths: bool,
+    scan_stack: bool,
+
+    /// Number of source code lines to print around each backtrace frame's location [default: 0]
+    #[structopt(long)]
+    source_context: Option<u32>,
+
+    /// Backtrace verbosity: `short` hides runtime frames, `full` prints all frames [default: from
+    /// `RUST_BACKTRACE`, `full` unless it's set to `short` or `1`]
+    #[structopt(long)]
+    backtrace: Option<backtrace::Verbosity>,
+
+    /// Print the arguments and local variables of each backtrace frame
+    #[structopt(long)]
+    show_locals: bool,
+
+    /// Save the raw RTT output to this file, to decode it later with `probe-run decode`
+    #[structopt(long, parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    log: LogOpts,
+
+    /// Output format of `--list-chips` and `--list-probes`: `text` or `json`
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
 
     /// Arguments passed after the ELF file path are discarded
     #[structopt(name = "REST")]
     _rest: Vec<String>,
 }
 
+/// Decodes the RTT output recorded with `probe-run --record`
+#[derive(StructOpt)]
+#[structopt(name = "probe-run decode", bin_name = "probe-run decode")]
+struct DecodeOpts {
+    /// Path to the ELF file of the program that produced the recording.
+    #[structopt(long, parse(from_os_str))]
+    elf: PathBuf,
+
+    /// Path to the recording.
+    #[structopt(name = "RECORDING", parse(from_os_str))]
+    recording: PathBuf,
+
+    /// Enable more verbose logging.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u32,
+
+    #[structopt(flatten)]
+    log: LogOpts,
+}
+
+// Options that control how logs and file locations are printed
+// NOTE not a doc comment because it would replace the `about` text of the commands
+#[derive(StructOpt)]
+struct LogOpts {
+    /// Whether to shorten paths (e.g. to crates.io dependencies) in backtraces and defmt logs
+    #[structopt(long)]
+    shorten_paths: bool,
+
+    /// Print file locations as terminal hyperlinks; optionally takes an editor URL template
+    /// such as `vscode://file{path}:{line}:{col}` [default: `file://{path}`]
+    #[structopt(long, env = "PROBE_RUN_HYPERLINKS", require_equals = true)]
+    hyperlinks: Option<Option<String>>,
+
+    /// Only print the defmt logs that pass this filter, e.g. `warn,my_crate::radio=trace`
+    #[structopt(long, env = "PROBE_RUN_DEFMT_FILTER")]
+    defmt_filter: Option<defmt_filter::Filter>,
+
+    /// Prefix each log line with the host time at which it was received: `elapsed` since reset
+    /// (default) or `wall` clock time (UTC), followed by the time since the previous line
+    #[structopt(long, env = "PROBE_RUN_HOST_TIMESTAMPS", require_equals = true)]
+    host_timestamps: Option<Option<host_timestamp::Mode>>,
+
+    /// Format of the log lines, e.g. `{timestamp} {level} {message}`, or `logfmt`. Placeholders:
+    /// `{timestamp}`, `{level}`, `{message}`, `{file}`, `{line}`, `{module}`, `{channel}`, `{core}`
+    #[structopt(long, env = "PROBE_RUN_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+impl Opts {
+    /// Fills in the settings that were not passed on the command line from the project
+    /// configuration
+    fn apply_config(&mut self, config: &Config) -> anyhow::Result<()> {
+        self.chip = self.chip.take().or_else(|| config.chip.clone());
+        self.chip_description_path = self
+            .chip_description_path
+            .take()
+            .or_else(|| config.chip_description_path.clone());
+        self.probe = self.probe.take().or_else(|| config.probe.clone());
+        self.speed = self.speed.or(config.speed);
+        self.connect_under_reset |= config.connect_under_reset.unwrap_or(false);
+        if let (None, Some(rtt_mode)) = (&self.rtt_mode, &config.rtt.mode) {
+            self.rtt_mode = Some(rtt_mode.parse()?);
+        }
+        self.ram |= config.ram.unwrap_or(false);
+        self.verify |= config.verify.unwrap_or(false);
+        // NOTE `conflicts_with_all` only checks the command line
+        if self.ram && (self.verify || self.no_flash || self.attach) {
+            let flag = if self.verify {
+                "verify"
+            } else if self.no_flash {
+                "no-flash"
+            } else {
+                "attach"
+            };
+            bail!(
+                "`ram` and `{}` can't be used together (check the command line and the project configuration)",
+                flag
+            );
+        }
+
+        if let (None, Some(backtrace)) = (self.backtrace, &config.backtrace) {
+            self.backtrace = Some(backtrace.parse()?);
+        }
+        self.force_backtrace |= config.force_backtrace.unwrap_or(false);
+        self.max_backtrace_len = self.max_backtrace_len.or(config.max_backtrace_len);
+        self.scan_stack |= config.scan_stack.unwrap_or(false);
+        self.source_context = self.source_context.or(config.source_context);
+        self.show_locals |= config.show_locals.unwrap_or(false);
+
+        self.log.apply_config(config)
+    }
+}
+
+impl LogOpts {
+    fn apply_config(&mut self, config: &Config) -> anyhow::Result<()> {
+        self.shorten_paths |= config.shorten_paths.unwrap_or(false);
+
+        if let (None, Some(defmt_filter)) = (&self.defmt_filter, &config.defmt_filter) {
+            self.defmt_filter = Some(defmt_filter.parse()?);
+        }
+
+        if let (None, Some(mode)) = (self.host_timestamps, &config.host_timestamps) {
+            self.host_timestamps = Some(Some(mode.parse()?));
+        }
+
+        if let (None, Some(log_format)) = (&self.log_format, &config.log_format) {
+            self.log_format = Some(log_format.parse()?);
+        }
+
+        if self.hyperlinks.is_none() {
+            self.hyperlinks = match &config.hyperlinks {
+                Some(config::Hyperlinks::Enabled(true)) => Some(None),
+                Some(config::Hyperlinks::Template(template)) => Some(Some(template.clone())),
+                Some(config::Hyperlinks::Enabled(false)) | None => None,
+            };
+        }
+
+        Ok(())
+    }
+
+    fn hyperlinks(&self) -> Option<hyperlink::Template> {
+        let template = self.hyperlinks.as_ref()?;
+        // NOTE keep escape sequences out of CI logs and redirected output
+        if !io::stdout().is_terminal() {
+            log::debug!("stdout is not a terminal; not printing hyperlinks");
+            return None;
+        }
+
+        Some(hyperlink::Template::new(
+            template.clone().unwrap_or_default(),
+        ))
+    }
+
+    fn settings<'a>(
+        &'a self,
+        table: Option<&'a decoder::Table>,
+        locs: Option<&'a decoder::Locations>,
+        current_dir: &'a Path,
+        hyperlinks: Option<&'a hyperlink::Template>,
+    ) -> logs::Settings<'a> {
+        logs::Settings {
+            table,
+            locs,
+            current_dir,
+            shorten_paths: self.shorten_paths,
+            hyperlinks,
+            defmt_filter: self.defmt_filter.as_ref(),
+            host_timestamps: self.host_timestamps.map(|mode| mode.unwrap_or_default()),
+            log_format: self.log_format.as_ref(),
+        }
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     notmain().map(|code| process::exit(code))
 }
 
-fn notmain() -> anyhow::Result<i32> {
-    let opts: Opts = Opts::from_args();
-    let verbose = opts.verbose;
-
+fn init_logger(verbose: u32) {
     defmt_decoder::log::init_logger(verbose >= 1, move |metadata| {
         if defmt_decoder::log::is_defmt_frame(metadata) {
             true // We want to display *all* defmt frames.
@@ -127,27 +362,76 @@ fn notmain() -> anyhow::Result<i32> {
             }
         }
     });
+}
+
+fn notmain() -> anyhow::Result<i32> {
+    if env::args_os().nth(1).as_deref() == Some(OsStr::new("decode")) {
+        let opts = DecodeOpts::from_iter(env::args_os().skip(1));
+        init_logger(opts.verbose);
+        return decode(opts);
+    }
+
+    let mut opts: Opts = Opts::from_args();
+    init_logger(opts.verbose);
 
     if opts.version {
         print_version();
         return Ok(EXIT_SUCCESS);
-    } else if opts.list_probes {
-        print_probes(Probe::list_all());
+    }
+
+    let config = Config::load(opts.elf.as_deref()).and_then(|config| {
+        opts.apply_config(&config)?;
+        Ok(config)
+    });
+    let config = match config {
+        Ok(config) => config,
+        // NOTE the lists help to fix the configuration, so they must not depend on it
+        Err(e) if opts.list_chips.is_some() || opts.list_probes => {
+            log::warn!("ignoring the project configuration: {:#}", e);
+            Config::default()
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(path) = &opts.chip_description_path {
+        registry::add_target_from_yaml(path)
+            .with_context(|| format!("failed to load chip description from {}", path.display()))?;
+    }
+
+    if let Some(pattern) = &opts.list_chips {
+        chips::print(pattern.as_deref(), opts.format)?;
         return Ok(EXIT_SUCCESS);
-    } else if opts.list_chips {
-        print_chips();
+    } else if opts.list_probes {
+        probes::print(&Probe::list_all(), &config.probe_aliases, opts.format)?;
         return Ok(EXIT_SUCCESS);
     }
+    let halt_timeout = config
+        .timeouts
+        .halt
+        .map(Duration::from_millis)
+        .unwrap_or(TIMEOUT);
+    let main_timeout = config
+        .timeouts
+        .main
+        .map(Duration::from_millis)
+        .unwrap_or(MAIN_TIMEOUT);
 
     let force_backtrace = opts.force_backtrace;
-    let max_backtrace_len = opts.max_backtrace_len;
-    let shorten_paths = opts.shorten_paths;
+    let max_backtrace_len = opts.max_backtrace_len.unwrap_or(DEFAULT_MAX_BACKTRACE_LEN);
+    let shorten_paths = opts.log.shorten_paths;
+    let hyperlinks = opts.log.hyperlinks();
     let elf_path = opts.elf.as_deref().unwrap();
-    let chip = opts.chip.as_deref().unwrap();
+    let chip = opts.chip.as_deref().ok_or_else(|| {
+        anyhow!("no chip specified; use `--chip` or set `chip` in the project configuration")
+    })?;
     let bytes = fs::read(elf_path)?;
     let elf = ElfFile::parse(&bytes)?;
+    let arch_kind = Arch::kind(&bytes)?;
 
-    let target = probe_rs::config::registry::get_target_by_name(chip)?;
+    let target = match registry::get_target_by_name(chip) {
+        Ok(target) => target,
+        Err(RegistryError::ChipNotFound) => return Err(chips::not_found(chip)),
+        Err(e) => return Err(e.into()),
+    };
 
     // find and report the RAM region
     let mut ram_region = None;
@@ -180,32 +464,17 @@ fn notmain() -> anyhow::Result<i32> {
             )
         })?;
 
-    // Parse defmt_decoder-table from bytes
-    // * skip defmt version check, if `PROBE_RUN_IGNORE_VERSION` matches one of the options
-    let mut table = match env::var("PROBE_RUN_IGNORE_VERSION").as_deref() {
-        Ok("true") | Ok("1") => defmt_decoder::Table::parse_ignore_version(&bytes)?,
-        _ => defmt_decoder::Table::parse(&bytes)?,
-    };
-    // Extract the `Locations` from the table, if there is a table
-    let mut locs = None;
-    if let Some(table) = table.as_ref() {
-        let tmp = table.get_locations(&bytes)?;
-
-        if !table.is_empty() && tmp.is_empty() {
-            log::warn!("insufficient DWARF info; compile your program with `debug = 2` to enable location info");
-        } else if table.indices().all(|idx| tmp.contains_key(&(idx as u64))) {
-            locs = Some(tmp);
-        } else {
-            log::warn!("(BUG) location info is incomplete; it will be omitted from the output");
-        }
-    }
-    let locs = locs;
+    let (table, locs) = decoder::table(&bytes)?;
 
-    // sections used in cortex-m-rt
+    // sections used in cortex-m-rt and riscv-rt
     // NOTE we won't load `.uninit` so it is not included here
     // NOTE we don't load `.bss` because the app (cortex-m-rt) will zero it
-    let candidates = [".vector_table", ".text", ".rodata", ".data"];
+    let candidates: &[&str] = match arch_kind {
+        arch::Kind::CortexM => &[".vector_table", ".text", ".rodata", ".data"],
+        arch::Kind::Riscv => &[".init", ".trap", ".text", ".rodata", ".data"],
+    };
 
+    let segments = flash::segments(&bytes)?;
     let mut highest_ram_addr_in_use = 0;
     let mut debug_frame = None;
     let mut sections = vec![];
@@ -260,7 +529,11 @@ fn notmain() -> anyhow::Result<i32> {
                     });
                 }
 
-                sections.push(Section { start, data });
+                sections.push(Section {
+                    start,
+                    load_address: flash::load_address(&segments, start),
+                    data,
+                });
             }
         }
     }
@@ -277,10 +550,40 @@ fn notmain() -> anyhow::Result<i32> {
         })
         .collect::<Result<HashSet<_>, _>>()?;
 
-    let (rtt_addr, uses_heap, main) = get_rtt_heap_main_from(&elf)?;
+    let init_symbol = config.rtt.init_symbol.as_deref().unwrap_or("main");
+    let (rtt_addr, uses_heap, init) = get_rtt_heap_init_from(&elf, init_symbol);
+    let rtt_location = match (rtt_addr, config.rtt.scan_range) {
+        (Some(rtt_addr), _) => Some(ScanRegion::Exact(rtt_addr)),
+        (None, Some(range)) => Some(ScanRegion::Range(rtt::scan_range(
+            range,
+            &target.memory_map,
+        )?)),
+        (None, None) => None,
+    };
+    let rtt_attach = config
+        .rtt
+        .attach
+        .as_deref()
+        .map(str::parse::<rtt::Attach>)
+        .transpose()?;
+    let channel_index = config.rtt.channel.unwrap_or(0);
+    let rtt_modes = match opts.rtt_mode.take() {
+        // don't change the modes of a running program unless asked to
+        Some(modes) if opts.attach => modes.explicit(channel_index),
+        None if opts.attach => BTreeMap::new(),
+        modes => modes.unwrap_or_default().resolve(channel_index),
+    };
 
-    let vector_table = vector_table.ok_or_else(|| anyhow!("`.vector_table` section is missing"))?;
-    log::debug!("vector table: {:x?}", vector_table);
+    let arch = match arch_kind {
+        arch::Kind::CortexM => {
+            let vector_table =
+                vector_table.ok_or_else(|| anyhow!("`.vector_table` section is missing"))?;
+            log::debug!("vector table: {:x?}", vector_table);
+            Arch::CortexM(vector_table)
+        }
+        arch::Kind::Riscv => Arch::Riscv(riscv::Info::from_elf(&elf)?),
+    };
+    let initial_sp = arch.initial_sp();
     let sp_ram_region = target
         .memory_map
         .iter()
@@ -289,7 +592,7 @@ fn notmain() -> anyhow::Result<i32> {
                 // NOTE stack is full descending; meaning the stack pointer can be
                 // `ORIGIN(RAM) + LENGTH(RAM)`
                 let range = region.range.start..=region.range.end;
-                if range.contains(&vector_table.initial_sp) {
+                if range.contains(&initial_sp) {
                     Some(region)
                 } else {
                     None
@@ -300,12 +603,18 @@ fn notmain() -> anyhow::Result<i32> {
         .next()
         .cloned();
 
-    let probes = Probe::list_all();
+    if opts.ram {
+        if arch_kind != arch::Kind::CortexM {
+            bail!("`--ram` is only supported on Cortex-M devices");
+        }
+        check_sections_in_ram(&sections, &target.memory_map)?;
+    }
+
+    let all_probes = Probe::list_all();
     let probes = if let Some(probe_opt) = opts.probe.as_deref() {
-        let selector = probe_opt.parse()?;
-        probes_filter(&probes, &selector)
+        ProbeFilter::parse(probe_opt, &config.probe_aliases)?.select(&all_probes)
     } else {
-        probes
+        all_probes.clone()
     };
 
     // ensure exactly one probe is found and open it
@@ -314,7 +623,7 @@ fn notmain() -> anyhow::Result<i32> {
     }
     log::debug!("found {} probes", probes.len());
     if probes.len() > 1 {
-        let _ = print_probes(probes);
+        probes::print(&all_probes, &config.probe_aliases, OutputFormat::Text)?;
         bail!("more than one probe found; use --probe to specify which one to use");
     }
     let mut probe = probes[0].open()?;
@@ -331,28 +640,118 @@ fn notmain() -> anyhow::Result<i32> {
     };
     log::debug!("started session");
 
-    if opts.no_flash {
+    // NOTE reading back the flash takes a while, so only do it if its result is used
+    let check_flash = if opts.ram || opts.force_flash {
+        false
+    } else if opts.no_flash || opts.attach {
+        // only `defmt` logs require the program on the device to match the ELF file
+        table.is_some()
+    } else {
+        true
+    };
+    let flash_is_up_to_date = check_flash
+        && match flash::is_up_to_date(&mut sess.core(0)?, &segments) {
+            Ok(is_up_to_date) => is_up_to_date,
+            Err(e) => {
+                log::debug!("failed to read back the flash contents: {}", e);
+                false
+            }
+        };
+
+    if opts.attach {
+        log::info!("skipped flashing; attaching to the running program");
+    } else if opts.ram {
+        log::info!("skipped flashing; the program will be loaded into RAM");
+    } else if opts.no_flash {
         log::info!("skipped flashing");
+    } else if flash_is_up_to_date {
+        log::info!("skipped flashing; the device already holds this program");
     } else {
         // program lives in Flash
         let size = program_size_of(&elf);
         log::info!("flashing program ({:.02} KiB)", size as f64 / 1024.0);
-        flashing::download_file(&mut sess, elf_path, Format::Elf)?;
-        log::info!("success!");
+        let progress = flash::Progress::new();
+        let flash_progress = progress.flash_progress();
+        flashing::download_file_with_options(
+            &mut sess,
+            elf_path,
+            Format::Elf,
+            DownloadOptions {
+                progress: Some(&flash_progress),
+                keep_unwritten_bytes: false,
+            },
+        )?;
+
+        if opts.verify {
+            let total = segments
+                .iter()
+                .map(|segment| segment.data.len())
+                .sum::<usize>();
+            if let Some(mismatch) = flash::verify(&mut sess.core(0)?, &segments, &progress)? {
+                bail!(
+                    "flash verification failed: {} of {} bytes differ from the ELF file; first \
+                    mismatch at 0x{:08X} (expected 0x{:02X}, read 0x{:02X})",
+                    mismatch.count,
+                    total,
+                    mismatch.address,
+                    mismatch.expected,
+                    mismatch.actual
+                );
+            }
+        }
+
+        let report = progress.report();
+        log::info!(
+            "success! flashing took {:.2}s ({})",
+            report.total_seconds(),
+            report
+        );
     }
 
     let mut canary = None;
-    {
+    let reset;
+    let attach;
+    let mut rtt_modes_set = false;
+    let mut catches_hard_faults = false;
+    if opts.attach {
+        let mut core = sess.core(0)?;
+        // NOTE the program is neither reset nor stopped at `main`; timestamps count from now
+        reset = (Instant::now(), SystemTime::now());
+        attach = rtt::Attach::Poll;
+        if let Some(fault_handler) = arch.fault_handler() {
+            core.set_hw_breakpoint(fault_handler)?;
+        }
+        if let Arch::Riscv(_) = arch {
+            // NOTE `probe-rs` halts RISC-V cores when it attaches to them
+            core.run()?;
+        }
+    } else {
         let mut core = sess.core(0)?;
-        core.reset_and_halt(TIMEOUT)?;
+        core.reset_and_halt(halt_timeout)?;
+        reset = (Instant::now(), SystemTime::now());
+
+        if let (true, Arch::CortexM(vector_table)) = (opts.ram, &arch) {
+            let size = sections
+                .iter()
+                .map(|section| section.data.len() * 4)
+                .sum::<usize>();
+            log::info!(
+                "loading program into RAM ({:.02} KiB)",
+                size as f64 / 1024.0
+            );
+            for section in &sections {
+                core.write_32(section.load_address, &section.data)?;
+            }
+            cortexm::boot_from(&mut core, vector_table)?;
+        }
 
         // Decide if and where to place the stack canary.
         if let Some(ram) = &ram_region {
             // Initial SP must be past canary location.
-            let initial_sp_makes_sense = ram.range.contains(&(vector_table.initial_sp - 1))
-                && highest_ram_addr_in_use < vector_table.initial_sp;
+            let initial_sp_makes_sense =
+                ram.range.contains(&(initial_sp - 1)) && highest_ram_addr_in_use < initial_sp;
             if highest_ram_addr_in_use != 0 && !uses_heap && initial_sp_makes_sense {
-                let stack_available = vector_table.initial_sp - highest_ram_addr_in_use - 1;
+                let stack_available = initial_sp - highest_ram_addr_in_use - 1;
 
                 // We consider >90% stack usage a potential stack overflow, but don't go beyond 1 kb since
                 // filling a lot of RAM is slow (and 1 kb should be "good enough" for what we're doing).
@@ -362,7 +761,7 @@ fn notmain() -> anyhow::Result<i32> {
                     "{} bytes of stack available (0x{:08X}-0x{:08X}), using {} byte canary to detect overflows",
                     stack_available,
                     highest_ram_addr_in_use + 1,
-                    vector_table.initial_sp,
+                    initial_sp,
                     canary_size,
                 );
 
@@ -375,25 +774,64 @@ fn notmain() -> anyhow::Result<i32> {
         }
 
         log::debug!("starting device");
-        if core.get_available_breakpoint_units()? == 0 {
-            if rtt_addr.is_some() {
-                bail!("RTT not supported on device without HW breakpoints");
-            } else {
-                log::warn!("device doesn't support HW breakpoints; HardFault will NOT make `probe-run` exit with an error code");
+        let has_breakpoints = core.get_available_breakpoint_units()? != 0;
+        // NOTE the breakpoint unit may not reach a program loaded with `--ram`
+        let can_break_in_program = match (opts.ram, arch.fault_handler()) {
+            (true, Some(fault_handler)) => cortexm::can_break_at(&mut core, fault_handler)?,
+            _ => true,
+        };
+        attach = match rtt_attach {
+            Some(rtt::Attach::Breakpoint) if !has_breakpoints && rtt_location.is_some() => {
+                bail!("RTT can't be attached at a breakpoint on a device without HW breakpoints; set `attach = \"poll\"` in the `rtt` configuration")
+            }
+            Some(rtt::Attach::Breakpoint) if !can_break_in_program && rtt_location.is_some() => {
+                bail!("RTT can't be attached at a breakpoint because the device's HW breakpoints can't stop a program in RAM; set `attach = \"poll\"` in the `rtt` configuration")
+            }
+            Some(attach) => attach,
+            None if !has_breakpoints && rtt_location.is_some() => {
+                log::info!("device doesn't support HW breakpoints; attaching to RTT while the program runs, so its first logs may be lost");
+                rtt::Attach::Poll
+            }
+            None if !can_break_in_program && rtt_location.is_some() => {
+                log::info!("the device's HW breakpoints can't stop a program in RAM; attaching to RTT while the program runs, so its first logs may be lost");
+                rtt::Attach::Poll
             }
+            None => rtt::Attach::Breakpoint,
+        };
+        if !has_breakpoints {
+            log::warn!("device doesn't support HW breakpoints; HardFault will NOT make `probe-run` exit with an error code");
+        }
+
+        if let (Some(location), rtt::Attach::Poll) = (&rtt_location, attach) {
+            // the core is still halted after the reset
+            rtt::clear_ids(&mut core, location)?;
         }
 
-        if let Some(rtt) = rtt_addr {
-            core.set_hw_breakpoint(main)?;
+        if rtt_location.is_some() && attach == rtt::Attach::Breakpoint {
+            let init = init.ok_or_else(|| anyhow!("`{}` symbol not found", init_symbol))?;
+            core.set_hw_breakpoint(init)?;
             core.run()?;
-            core.wait_for_core_halted(Duration::from_secs(5))?;
-            const OFFSET: u32 = 44;
-            const FLAG: u32 = 2; // BLOCK_IF_FULL
-            core.write_word_32(rtt + OFFSET, FLAG)?;
-            core.clear_hw_breakpoint(main)?;
+            core.wait_for_core_halted(main_timeout)?;
+            if let Some(rtt) = rtt_addr {
+                // NOTE if any mode can't be set yet, all of them are set once RTT is attached
+                let mut all_set = true;
+                for (channel, mode) in &rtt_modes {
+                    all_set &= rtt::set_mode(&mut core, rtt, *channel, *mode)?;
+                }
+                rtt_modes_set = all_set;
+            }
+            core.clear_hw_breakpoint(init)?;
         }
 
-        core.set_hw_breakpoint(cortexm::clear_thumb_bit(vector_table.hard_fault))?;
+        if let Some(fault_handler) = arch.fault_handler() {
+            if can_break_in_program {
+                core.set_hw_breakpoint(fault_handler)?;
+            } else {
+                log::debug!("halting on HardFault with a vector catch instead of a breakpoint");
+                cortexm::catch_hard_faults(&mut core, true)?;
+                catches_hard_faults = true;
+            }
+        }
         core.run()?;
     }
     let canary = canary;
@@ -403,24 +841,47 @@ fn notmain() -> anyhow::Result<i32> {
     let exit = Arc::new(AtomicBool::new(false));
     let sigid = signal_hook::flag::register(signal::SIGINT, exit.clone())?;
 
+    // NOTE `probe-rs-rtt` requires the session to be shared through an `Arc<Mutex<_>>`
+    #[allow(clippy::arc_with_non_send_sync)]
     let sess = Arc::new(Mutex::new(sess));
-    let mut logging_channel = setup_logging_channel(rtt_addr, sess.clone())?;
+    let rtt_setup = rtt_location.map(|location| RttSetup {
+        location,
+        poll_timeout: Some(main_timeout).filter(|_| attach == rtt::Attach::Poll),
+        modes: Some(&rtt_modes).filter(|_| !rtt_modes_set),
+    });
+    let mut logging_channel = setup_logging_channel(rtt_setup, sess.clone(), &config.rtt)?;
+    // NOTE read back, because the requested mode may not have been set
+    let logging_mode = match &logging_channel {
+        Some(logging_channel) => logging_channel.mode()?.into(),
+        None => rtt::Mode::BlockIfFull,
+    };
+    log::debug!(
+        "RTT channel {} is in `{}` mode",
+        channel_index,
+        logging_mode
+    );
+    let channel_len = logging_channel
+        .as_ref()
+        .map(|ch| ch.buffer_size())
+        .unwrap_or_default();
 
     // `defmt-rtt` names the channel "defmt", so enable defmt decoding in that case.
     let use_defmt = logging_channel
         .as_ref()
-        .map_or(false, |ch| ch.name() == Some("defmt"));
+        .is_some_and(|ch| ch.name() == Some("defmt"));
 
-    if use_defmt && opts.no_flash {
+    if use_defmt && table.is_none() {
+        bail!("\"defmt\" RTT channel is in use, but the firmware binary contains no defmt data");
+    } else if use_defmt && (opts.no_flash || opts.attach) && !flash_is_up_to_date {
+        let flag = if opts.attach {
+            "--attach"
+        } else {
+            "--no-flash"
+        };
         bail!(
-            "attempted to use `--no-flash` and `defmt` logging -- this combination is not allowed. Remove the `--no-flash` flag"
+            "the program on the device differs from the ELF file so its `defmt` logs can't be decoded -- remove the `{}` flag",
+            flag
         );
-    } else if use_defmt && table.is_none() {
-        bail!("\"defmt\" RTT channel is in use, but the firmware binary contains no defmt data");
-    }
-
-    if !use_defmt {
-        table = None;
     }
 
     print_separator();
@@ -428,14 +889,45 @@ fn notmain() -> anyhow::Result<i32> {
     // wait for breakpoint
     let stdout = io::stdout();
     let mut stdout = stdout.lock();
-    let mut read_buf = [0; 1024];
-    let mut frames = vec![];
+    let mut read_buf = if logging_mode.drops_data() {
+        // NOTE the reads must be able to empty the channel's buffer to notice when it was full
+        rtt::ReadBuffer::whole_channel(channel_len)
+    } else {
+        rtt::ReadBuffer::new(channel_len)
+    };
+    let mut overflows = rtt::Overflows::new(channel_len);
+    let mut halt_polling = rtt::HaltPolling::new();
+    let mut rtt_stats = rtt::Stats::new();
     let mut was_halted = false;
     let current_dir = std::env::current_dir()?;
+    let log_settings = opts.log.settings(
+        table.as_ref(),
+        locs.as_ref(),
+        &current_dir,
+        hyperlinks.as_ref(),
+    );
+    let channel_name = logging_channel
+        .as_ref()
+        .and_then(|ch| ch.name())
+        .map(str::to_string)
+        .unwrap_or_else(|| channel_index.to_string());
+    let mut channel = logs::Channel::new(&log_settings, channel_name, use_defmt, reset.1);
+
+    let mut recorder = match &opts.record {
+        Some(path) => {
+            let mut recorder = Recorder::create(path, reset.1)?;
+            if let Some(logging_channel) = &logging_channel {
+                recorder.channel(channel_index, logging_channel.name())?;
+            }
+            Some(recorder)
+        }
+        None => None,
+    };
 
     while !exit.load(Ordering::Relaxed) {
+        let mut received_data = false;
         if let Some(logging_channel) = &mut logging_channel {
-            let num_bytes_read = match logging_channel.read(&mut read_buf) {
+            let num_bytes_read = match logging_channel.read(read_buf.as_mut()) {
                 Ok(n) => n,
                 Err(e) => {
                     eprintln!("RTT error: {}", e);
@@ -444,62 +936,32 @@ fn notmain() -> anyhow::Result<i32> {
             };
 
             if num_bytes_read != 0 {
-                if let Some(table) = table.as_ref() {
-                    frames.extend_from_slice(&read_buf[..num_bytes_read]);
-
-                    loop {
-                        match table.decode(&frames) {
-                            Ok((frame, consumed)) => {
-                                // NOTE(`[]` indexing) all indices in `table` have already been
-                                // verified to exist in the `locs` map
-                                let loc = locs.as_ref().map(|locs| &locs[&frame.index()]);
-
-                                let (mut file, mut line, mut mod_path) = (None, None, None);
-                                if let Some(loc) = loc {
-                                    let path =
-                                        if let Ok(relpath) = loc.file.strip_prefix(&current_dir) {
-                                            relpath.display().to_string()
-                                        } else {
-                                            let dep_path = dep::Path::from_std_path(&loc.file);
-
-                                            if shorten_paths {
-                                                dep_path.format_short()
-                                            } else {
-                                                dep_path.format_highlight()
-                                            }
-                                        };
-
-                                    file = Some(path);
-                                    line = Some(loc.line as u32);
-                                    mod_path = Some(loc.module.clone());
-                                }
-
-                                // Forward the defmt frame to our logger.
-                                defmt_decoder::log::log_defmt(
-                                    &frame,
-                                    file.as_deref(),
-                                    line,
-                                    mod_path.as_deref(),
-                                );
-
-                                let num_frames = frames.len();
-                                frames.rotate_left(consumed);
-                                frames.truncate(num_frames - consumed);
-                            }
-                            Err(defmt_decoder::DecodeError::UnexpectedEof) => break,
-                            Err(defmt_decoder::DecodeError::Malformed) => {
-                                log::error!("failed to decode defmt data: {:x?}", frames);
-                                return Err(defmt_decoder::DecodeError::Malformed.into());
-                            }
-                        }
-                    }
-                } else {
-                    stdout.write_all(&read_buf[..num_bytes_read])?;
-                    stdout.flush()?;
+                received_data = true;
+                rtt_stats.record(num_bytes_read);
+                if logging_mode.drops_data()
+                    && overflows.check(num_bytes_read)
+                    && overflows.count() == 1
+                {
+                    log::warn!(
+                        "the buffer of RTT channel {} is full; the program may drop logs in `{}` mode",
+                        channel_index,
+                        logging_mode
+                    );
                 }
+                let bytes = read_buf.filled(num_bytes_read);
+                let elapsed = reset.0.elapsed();
+                if let Some(recorder) = &mut recorder {
+                    recorder.data(channel_index, elapsed, bytes)?;
+                }
+                channel.print(bytes, elapsed, &mut stdout)?;
             }
         }
 
+        // NOTE checking requires the session lock, so don't do it after every read
+        if !halt_polling.is_due(received_data) {
+            continue;
+        }
+
         let mut sess = sess.lock().unwrap();
         let mut core = sess.core(0)?;
         let is_halted = core.core_halted()?;
@@ -509,7 +971,19 @@ fn notmain() -> anyhow::Result<i32> {
         }
         was_halted = is_halted;
     }
+    channel.finish(reset.0.elapsed(), &mut stdout)?;
     drop(stdout);
+    channel.decode_errors().report();
+    if logging_channel.is_some() {
+        log::debug!("RTT: {}", rtt_stats.report());
+    }
+    if overflows.count() != 0 {
+        log::warn!(
+            "the buffer of RTT channel {} was found full ({} full-buffer events); logs may have been lost",
+            channel_index,
+            overflows.count()
+        );
+    }
 
     // Make any incoming SIGINT terminate the process.
     // Due to https://github.com/vorner/signal-hook/issues/97, this will result in SIGABRT, but you
@@ -522,20 +996,20 @@ fn notmain() -> anyhow::Result<i32> {
 
     if exit.load(Ordering::Relaxed) {
         // Ctrl-C was pressed; stop the microcontroller.
-        core.halt(TIMEOUT)?;
+        core.halt(halt_timeout)?;
     }
 
     // TODO move into own function?
     let mut canary_touched = false;
     if let Some((addr, len)) = canary {
         let mut buf = vec![0; len as usize];
-        core.read_8(addr as u32, &mut buf)?;
+        core.read_8(addr, &mut buf)?;
 
         if let Some(pos) = buf.iter().position(|b| *b != STACK_CANARY) {
             let touched_addr = addr + pos as u32;
             log::debug!("canary was touched at 0x{:08X}", touched_addr);
 
-            let min_stack_usage = vector_table.initial_sp - touched_addr;
+            let min_stack_usage = initial_sp - touched_addr;
             log::warn!(
                 "program has used at least {} bytes of stack space, data segments \
                 may be corrupted due to stack overflow",
@@ -558,19 +1032,38 @@ fn notmain() -> anyhow::Result<i32> {
         // TODO any other cases in which we should force a backtrace?
         force_backtrace: force_backtrace || canary_touched || halted_due_to_signal,
         shorten_paths,
+        scan_stack: opts.scan_stack,
+        source_context: opts.source_context.unwrap_or(0),
+        show_locals: opts.show_locals,
+        verbosity: opts
+            .backtrace
+            .unwrap_or_else(backtrace::Verbosity::from_env),
+        hyperlinks: hyperlinks.as_ref(),
     };
 
     let outcome = backtrace::print(
         &mut core,
         debug_frame,
         &elf,
-        &vector_table,
+        &arch,
         &sp_ram_region,
         &live_functions,
         &backtrace_settings,
     )?;
 
-    core.reset_and_halt(TIMEOUT)?;
+    if opts.resume {
+        if let Some(fault_handler) = arch.fault_handler() {
+            // NOTE a breakpoint would halt the program for good once `probe-run` is gone
+            core.clear_hw_breakpoint(fault_handler)?;
+        }
+        core.run()?;
+        log::info!("resumed the program");
+    } else if !opts.attach {
+        if catches_hard_faults {
+            cortexm::catch_hard_faults(&mut core, false)?;
+        }
+        core.reset_and_halt(halt_timeout)?;
+    }
 
     Ok(match outcome {
         Outcome::StackOverflow => {
@@ -588,6 +1081,67 @@ fn notmain() -> anyhow::Result<i32> {
     })
 }
 
+/// Prints a recording made with `--record`
+fn decode(mut opts: DecodeOpts) -> anyhow::Result<i32> {
+    let config = Config::load(Some(&opts.elf))?;
+    opts.log.apply_config(&config)?;
+
+    let bytes = fs::read(&opts.elf)?;
+    let (table, locs) = decoder::table(&bytes)?;
+    let file = File::open(&opts.recording)
+        .with_context(|| format!("failed to open recording {}", opts.recording.display()))?;
+    let recording = Recording::read(file)?;
+
+    let current_dir = env::current_dir()?;
+    let hyperlinks = opts.log.hyperlinks();
+    let log_settings = opts.log.settings(
+        table.as_ref(),
+        locs.as_ref(),
+        &current_dir,
+        hyperlinks.as_ref(),
+    );
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut channels = BTreeMap::new();
+    let mut last_elapsed = Duration::default();
+    for record in recording.records {
+        match record {
+            Record::Channel { index, name } => {
+                let use_defmt = name.as_deref() == Some("defmt");
+                let name = name.unwrap_or_else(|| index.to_string());
+                if use_defmt && table.is_none() {
+                    bail!("\"defmt\" RTT channel was recorded, but the ELF file contains no defmt data");
+                }
+                channels.insert(
+                    index,
+                    logs::Channel::new(&log_settings, name, use_defmt, recording.reset),
+                );
+            }
+            Record::Data {
+                index,
+                elapsed,
+                data,
+            } => match channels.get_mut(&index) {
+                Some(channel) => {
+                    last_elapsed = elapsed;
+                    channel.print(&data, elapsed, &mut stdout)?
+                }
+                None => bail!(
+                    "recording is corrupted (data for unknown channel {})",
+                    index
+                ),
+            },
+        }
+    }
+    for channel in channels.values_mut() {
+        channel.finish(last_elapsed, &mut stdout)?;
+        channel.decode_errors().report();
+    }
+
+    Ok(EXIT_SUCCESS)
+}
+
 fn program_size_of(file: &ElfFile) -> u64 {
     // `segments` iterates only over *loadable* segments,
     // which are the segments that will be loaded to Flash by probe-rs
@@ -600,25 +1154,52 @@ pub enum TopException {
     HardFault, // generic hard fault
 }
 
+/// How `setup_logging_channel` attaches to the RTT control block
+struct RttSetup<'a> {
+    location: ScanRegion,
+    /// Keep retrying for this long instead of `attach-retries` times, for programs that are running
+    poll_timeout: Option<Duration>,
+    /// Channel modes that still have to be set
+    modes: Option<&'a BTreeMap<usize, rtt::Mode>>,
+}
+
 fn setup_logging_channel(
-    rtt_addr: Option<u32>,
+    setup: Option<RttSetup>,
     sess: Arc<Mutex<Session>>,
+    config: &config::Rtt,
 ) -> anyhow::Result<Option<UpChannel>> {
-    if let Some(rtt_addr_res) = rtt_addr {
-        const NUM_RETRIES: usize = 10; // picked at random, increase if necessary
-        let mut rtt_res: Result<Rtt, probe_rs_rtt::Error> =
-            Err(probe_rs_rtt::Error::ControlBlockNotFound);
-
-        for try_index in 0..=NUM_RETRIES {
-            rtt_res = Rtt::attach_region(sess.clone(), &ScanRegion::Exact(rtt_addr_res));
-            match rtt_res {
-                Ok(_) => {
+    if let Some(setup) = setup {
+        let num_retries = config.attach_retries.unwrap_or(RTT_ATTACH_RETRIES);
+        let started = Instant::now();
+
+        let mut try_index = 0;
+        let mut rtt = loop {
+            match Rtt::attach_region(sess.clone(), &setup.location) {
+                Ok(mut rtt) => {
+                    // NOTE the ID may be written before the rest of the control block (see
+                    // `rtt::clear_ids`), so wait for the up channels while polling
+                    let polling = setup
+                        .poll_timeout
+                        .is_some_and(|timeout| started.elapsed() < timeout);
+                    if polling && rtt.up_channels().is_empty() {
+                        log::trace!("RTT control block has no up channels (yet). retrying");
+                        thread::sleep(RTT_POLL_INTERVAL);
+                        continue;
+                    }
                     log::debug!("Successfully attached RTT");
-                    break;
+                    break rtt;
                 }
                 Err(probe_rs_rtt::Error::ControlBlockNotFound) => {
-                    if try_index < NUM_RETRIES {
+                    let retry = match setup.poll_timeout {
+                        Some(timeout) => started.elapsed() < timeout,
+                        None => try_index < num_retries,
+                    };
+                    if retry {
                         log::trace!("Could not attach because the target's RTT control block isn't initialized (yet). retrying");
+                        try_index += 1;
+                        if setup.poll_timeout.is_some() {
+                            thread::sleep(RTT_POLL_INTERVAL);
+                        }
                     } else {
                         log::error!("Max number of RTT attach retries exceeded.");
                         return Err(anyhow!(probe_rs_rtt::Error::ControlBlockNotFound));
@@ -628,13 +1209,23 @@ fn setup_logging_channel(
                     return Err(anyhow!(e));
                 }
             }
+        };
+
+        for (index, mode) in setup.modes.into_iter().flatten() {
+            match rtt.up_channels().get(*index) {
+                Some(channel) => channel.set_mode(mode.channel_mode())?,
+                None => log::warn!(
+                    "can't set the mode of RTT up channel {}; it doesn't exist",
+                    index
+                ),
+            }
         }
 
-        let channel = rtt_res
-            .expect("unreachable") // this block is only executed when rtt was successfully attached before
+        let channel_index = config.channel.unwrap_or(0);
+        let channel = rtt
             .up_channels()
-            .take(0)
-            .ok_or_else(|| anyhow!("RTT up channel 0 not found"))?;
+            .take(channel_index)
+            .ok_or_else(|| anyhow!("RTT up channel {} not found", channel_index))?;
         Ok(Some(channel))
     } else {
         eprintln!("RTT logs not available; blocking until the device halts..");
@@ -642,85 +1233,34 @@ fn setup_logging_channel(
     }
 }
 
-struct ProbeFilter {
-    vid_pid: Option<(u16, u16)>,
-    serial: Option<String>,
+/// Format of the information printed by the `--list-*` flags
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
-impl FromStr for ProbeFilter {
+impl FromStr for OutputFormat {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let parts = s.split(':').collect::<Vec<_>>();
-        match &*parts {
-            [serial] => Ok(Self {
-                vid_pid: None,
-                serial: Some(serial.to_string()),
-            }),
-            [vid, pid] => Ok(Self {
-                vid_pid: Some((u16::from_str_radix(vid, 16)?, u16::from_str_radix(pid, 16)?)),
-                serial: None,
-            }),
-            [vid, pid, serial] => Ok(Self {
-                vid_pid: Some((u16::from_str_radix(vid, 16)?, u16::from_str_radix(pid, 16)?)),
-                serial: Some(serial.to_string()),
-            }),
-            _ => Err(anyhow!("invalid probe filter")),
-        }
-    }
-}
-
-fn probes_filter(probes: &[DebugProbeInfo], selector: &ProbeFilter) -> Vec<DebugProbeInfo> {
-    probes
-        .iter()
-        .filter(|&p| {
-            if let Some((vid, pid)) = selector.vid_pid {
-                if p.vendor_id != vid || p.product_id != pid {
-                    return false;
-                }
-            }
-
-            if let Some(serial) = &selector.serial {
-                if p.serial_number.as_deref() != Some(serial) {
-                    return false;
-                }
-            }
-
-            true
-        })
-        .cloned()
-        .collect()
-}
-
-fn print_chips() {
-    let registry = registry::families().expect("Could not retrieve chip family registry");
-    for chip_family in registry {
-        println!("{}\n    Variants:", chip_family.name);
-        for variant in chip_family.variants.iter() {
-            println!("        {}", variant.name);
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow!("invalid output format (expected `text` or `json`)")),
         }
     }
 }
 
-fn print_probes(probes: Vec<DebugProbeInfo>) {
-    if !probes.is_empty() {
-        println!("The following devices were found:");
-        probes
-            .iter()
-            .enumerate()
-            .for_each(|(num, link)| println!("[{}]: {:?}", num, link));
-    } else {
-        println!("No devices were found.");
-    }
-}
-
 /// The string reported by the `--version` flag
 fn print_version() {
     const VERSION: &str = env!("CARGO_PKG_VERSION"); // version from Cargo.toml e.g. "0.1.4"
     const HASH: &str = include_str!(concat!(env!("OUT_DIR"), "/git-info.txt")); // "" OR git hash e.g. "34019f8" -- this is generated in build.rs
     println!(
-        "{}{}\nsupported defmt version: {}",
-        VERSION, HASH, DEFMT_VERSION
+        "{}{}\nsupported defmt versions: {}",
+        VERSION,
+        HASH,
+        decoder::supported_versions().join(", ")
     );
 }
 
@@ -729,12 +1269,14 @@ fn print_separator() {
     println!("{}", "─".repeat(80).dimmed());
 }
 
-fn get_rtt_heap_main_from(
+/// Looks up the RTT control block, whether the heap is used and the address of `init_symbol`
+fn get_rtt_heap_init_from(
     elf: &ElfFile,
-) -> anyhow::Result<(Option<u32>, /* uses heap: */ bool, u32)> {
+    init_symbol: &str,
+) -> (Option<u32>, /* uses heap: */ bool, Option<u32>) {
     let mut rtt = None;
     let mut uses_heap = false;
-    let mut main = None;
+    let mut init = None;
 
     for symbol in elf.symbols() {
         let name = match symbol.name() {
@@ -743,7 +1285,9 @@ fn get_rtt_heap_main_from(
         };
 
         match name {
-            "main" => main = Some(cortexm::clear_thumb_bit(symbol.address() as u32)),
+            _ if name == init_symbol => {
+                init = Some(cortexm::clear_thumb_bit(symbol.address() as u32))
+            }
             "_SEGGER_RTT" => rtt = Some(symbol.address() as u32),
             "__rust_alloc" | "__rg_alloc" | "__rdl_alloc" | "malloc" if !uses_heap => {
                 log::debug!("symbol `{}` indicates heap is in use", name);
@@ -753,20 +1297,41 @@ fn get_rtt_heap_main_from(
         }
     }
 
-    Ok((
-        rtt,
-        uses_heap,
-        main.ok_or_else(|| anyhow!("`main` symbol not found"))?,
-    ))
+    (rtt, uses_heap, init)
 }
 
 /// ELF section to be loaded onto the target
 #[derive(Debug)]
 struct Section {
+    /// Run address
     start: u32,
+    /// Address the section's data is loaded to, e.g. the flash address of `.data`
+    load_address: u32,
     data: Vec<u32>,
 }
 
+/// Checks that a program linked for RAM can be loaded into the target's RAM
+fn check_sections_in_ram(sections: &[Section], memory_map: &[MemoryRegion]) -> anyhow::Result<()> {
+    for section in sections {
+        for address in &[section.start, section.load_address] {
+            let end = address + section.data.len() as u32 * 4;
+            let in_ram = memory_map.iter().any(|region| match region {
+                MemoryRegion::Ram(ram) => ram.range.start <= *address && end <= ram.range.end,
+                _ => false,
+            });
+            if !in_ram {
+                bail!(
+                    "0x{:08X}-0x{:08X} is not in RAM; `--ram` requires a program that is linked \
+                    for RAM (check the memory layout in `memory.x`)",
+                    address,
+                    end - 1
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 /// The contents of the vector table
 #[derive(Debug)]
 struct VectorTable {
@@ -0,0 +1,233 @@
+//! Listing and selecting debug probes
+
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::anyhow;
+use probe_rs::{DebugProbeInfo, DebugProbeType};
+use serde::Serialize;
+
+use crate::OutputFormat;
+
+/// Selects one or more of the connected probes
+#[derive(Debug, PartialEq)]
+pub(crate) enum ProbeFilter {
+    /// Position in the `--list-probes` output, written as `#<index>` so that it can't be mistaken
+    /// for a serial number
+    Index(usize),
+    Type(DebugProbeType),
+    Ids {
+        vid_pid: Option<(u16, u16)>,
+        serial: Option<String>,
+    },
+}
+
+impl ProbeFilter {
+    /// Parses `s`, which may be the name of one of the probe `aliases`
+    pub(crate) fn parse(s: &str, aliases: &BTreeMap<String, String>) -> anyhow::Result<Self> {
+        aliases.get(s).map(|alias| &**alias).unwrap_or(s).parse()
+    }
+
+    pub(crate) fn select(&self, probes: &[DebugProbeInfo]) -> Vec<DebugProbeInfo> {
+        probes
+            .iter()
+            .enumerate()
+            .filter(|(index, probe)| self.matches(*index, probe))
+            .map(|(_, probe)| probe.clone())
+            .collect()
+    }
+
+    fn matches(&self, index: usize, probe: &DebugProbeInfo) -> bool {
+        match self {
+            ProbeFilter::Index(wanted) => index == *wanted,
+            ProbeFilter::Type(probe_type) => probe.probe_type == *probe_type,
+            ProbeFilter::Ids { vid_pid, serial } => {
+                if let Some((vid, pid)) = vid_pid {
+                    if probe.vendor_id != *vid || probe.product_id != *pid {
+                        return false;
+                    }
+                }
+
+                if let Some(serial) = serial {
+                    if probe.serial_number.as_deref() != Some(serial) {
+                        return false;
+                    }
+                }
+
+                true
+            }
+        }
+    }
+}
+
+impl FromStr for ProbeFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(index) = s.strip_prefix('#') {
+            return Ok(ProbeFilter::Index(index.parse().map_err(|_| {
+                anyhow!("invalid probe index `{}` (expected e.g. `#1`)", s)
+            })?));
+        }
+
+        if let Some(probe_type) = parse_type(s) {
+            return Ok(ProbeFilter::Type(probe_type));
+        }
+
+        let parts = s.split(':').collect::<Vec<_>>();
+        match &*parts {
+            [serial] => Ok(ProbeFilter::Ids {
+                vid_pid: None,
+                serial: Some(serial.to_string()),
+            }),
+            [vid, pid] => Ok(ProbeFilter::Ids {
+                vid_pid: Some((u16::from_str_radix(vid, 16)?, u16::from_str_radix(pid, 16)?)),
+                serial: None,
+            }),
+            [vid, pid, serial] => Ok(ProbeFilter::Ids {
+                vid_pid: Some((u16::from_str_radix(vid, 16)?, u16::from_str_radix(pid, 16)?)),
+                serial: Some(serial.to_string()),
+            }),
+            _ => Err(anyhow!("invalid probe filter")),
+        }
+    }
+}
+
+fn parse_type(s: &str) -> Option<DebugProbeType> {
+    Some(match &*s.to_lowercase() {
+        "daplink" | "cmsis-dap" | "cmsisdap" => DebugProbeType::DAPLink,
+        "ftdi" => DebugProbeType::FTDI,
+        "jlink" | "j-link" => DebugProbeType::JLink,
+        "stlink" | "st-link" => DebugProbeType::STLink,
+        _ => return None,
+    })
+}
+
+fn type_name(probe_type: &DebugProbeType) -> &'static str {
+    match probe_type {
+        DebugProbeType::DAPLink => "daplink",
+        DebugProbeType::FTDI => "ftdi",
+        DebugProbeType::JLink => "jlink",
+        DebugProbeType::STLink => "stlink",
+    }
+}
+
+/// Prints the probes together with the names of the `aliases` that select them
+pub(crate) fn print(
+    probes: &[DebugProbeInfo],
+    aliases: &BTreeMap<String, String>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let aliases_of = |index: usize, probe: &DebugProbeInfo| {
+        aliases
+            .iter()
+            .filter(|(_, filter)| match filter.parse::<ProbeFilter>() {
+                Ok(filter) => filter.matches(index, probe),
+                Err(_) => false,
+            })
+            .map(|(name, _)| &**name)
+            .collect::<Vec<_>>()
+    };
+
+    match format {
+        OutputFormat::Text => {
+            if probes.is_empty() {
+                println!("No devices were found.");
+                return Ok(());
+            }
+
+            println!("The following devices were found:");
+            for (index, probe) in probes.iter().enumerate() {
+                let serial = probe
+                    .serial_number
+                    .as_ref()
+                    .map(|serial| format!("Serial: {}, ", serial))
+                    .unwrap_or_default();
+                let aliases = aliases_of(index, probe);
+                let aliases = if aliases.is_empty() {
+                    String::new()
+                } else {
+                    format!(" [{}]", aliases.join(", "))
+                };
+                println!(
+                    "[{}]: {} (VID: {:04x}, PID: {:04x}, {}{}){}",
+                    index,
+                    probe.identifier,
+                    probe.vendor_id,
+                    probe.product_id,
+                    serial,
+                    type_name(&probe.probe_type),
+                    aliases
+                );
+            }
+        }
+
+        OutputFormat::Json => {
+            let probes = probes
+                .iter()
+                .enumerate()
+                .map(|(index, probe)| ProbeJson {
+                    index,
+                    identifier: &probe.identifier,
+                    vendor_id: format!("{:04x}", probe.vendor_id),
+                    product_id: format!("{:04x}", probe.product_id),
+                    serial_number: probe.serial_number.as_deref(),
+                    probe_type: type_name(&probe.probe_type),
+                    aliases: aliases_of(index, probe),
+                })
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::to_string_pretty(&probes)?);
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ProbeJson<'a> {
+    index: usize,
+    identifier: &'a str,
+    vendor_id: String,
+    product_id: String,
+    serial_number: Option<&'a str>,
+    #[serde(rename = "type")]
+    probe_type: &'static str,
+    aliases: Vec<&'a str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("#1", ProbeFilter::Index(1))]
+    #[case("1", ProbeFilter::Ids { vid_pid: None, serial: Some("1".to_string()) })]
+    #[case("STLink", ProbeFilter::Type(DebugProbeType::STLink))]
+    #[case("cmsis-dap", ProbeFilter::Type(DebugProbeType::DAPLink))]
+    #[case("000683", ProbeFilter::Ids { vid_pid: None, serial: Some("000683".to_string()) })]
+    #[case("0483:374b", ProbeFilter::Ids { vid_pid: Some((0x0483, 0x374b)), serial: None })]
+    #[case(
+        "0483:374b:0671FF",
+        ProbeFilter::Ids { vid_pid: Some((0x0483, 0x374b)), serial: Some("0671FF".to_string()) }
+    )]
+    fn filters(#[case] input: &str, #[case] expected: ProbeFilter) {
+        assert_eq!(expected, input.parse::<ProbeFilter>().unwrap());
+    }
+
+    #[test]
+    fn alias() {
+        let mut aliases = BTreeMap::new();
+        aliases.insert("board-a".to_string(), "0483:374b:0671FF".to_string());
+
+        let expected = ProbeFilter::Ids {
+            vid_pid: Some((0x0483, 0x374b)),
+            serial: Some("0671FF".to_string()),
+        };
+        assert_eq!(expected, ProbeFilter::parse("board-a", &aliases).unwrap());
+        assert_eq!(
+            ProbeFilter::Type(DebugProbeType::JLink),
+            ProbeFilter::parse("jlink", &aliases).unwrap()
+        );
+    }
+}
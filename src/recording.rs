@@ -0,0 +1,239 @@
+//! Recordings of the raw RTT output, for decoding it later with `probe-run decode`
+//!
+//! A recording starts with `MAGIC`, a version byte and the time of the device reset in
+//! microseconds since the UNIX epoch. It is followed by records, all integers little-endian:
+//!
+//! - `0, index: u32, name length: u32, name` announces an RTT channel
+//! - `1, index: u32, microseconds since reset: u64, length: u32, data` is data read from a channel
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context as _};
+
+const MAGIC: &[u8; 8] = b"PRRTTREC";
+const VERSION: u8 = 1;
+
+const CHANNEL: u8 = 0;
+const DATA: u8 = 1;
+
+/// Writes a recording
+pub(crate) struct Recorder<W: Write> {
+    writer: W,
+}
+
+impl Recorder<BufWriter<File>> {
+    pub(crate) fn create(path: &Path, reset: SystemTime) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create recording {}", path.display()))?;
+        Ok(Self::new(BufWriter::new(file), reset)?)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    fn new(mut writer: W, reset: SystemTime) -> io::Result<Self> {
+        let reset = reset.duration_since(UNIX_EPOCH).unwrap_or_default();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(reset.as_micros() as u64).to_le_bytes())?;
+        Ok(Self { writer })
+    }
+
+    pub(crate) fn channel(&mut self, index: usize, name: Option<&str>) -> io::Result<()> {
+        let name = name.unwrap_or_default();
+        self.writer.write_all(&[CHANNEL])?;
+        self.writer.write_all(&(index as u32).to_le_bytes())?;
+        self.writer.write_all(&(name.len() as u32).to_le_bytes())?;
+        self.writer.write_all(name.as_bytes())?;
+        self.writer.flush()
+    }
+
+    /// Records `data` read from channel `index`, `elapsed` time after the reset
+    pub(crate) fn data(&mut self, index: usize, elapsed: Duration, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&[DATA])?;
+        self.writer.write_all(&(index as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(elapsed.as_micros() as u64).to_le_bytes())?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        // keep the recording usable if `probe-run` is killed
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Recording {
+    /// When the device was reset
+    pub(crate) reset: SystemTime,
+    pub(crate) records: Vec<Record>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Record {
+    Channel {
+        index: usize,
+        name: Option<String>,
+    },
+    Data {
+        index: usize,
+        /// Time since the reset
+        elapsed: Duration,
+        data: Vec<u8>,
+    },
+}
+
+impl Recording {
+    pub(crate) fn read(mut reader: impl Read) -> anyhow::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let mut bytes = Bytes(&bytes);
+
+        if bytes.take(MAGIC.len()) != Some(MAGIC) {
+            bail!("not a `probe-run` recording");
+        }
+        match bytes.u8() {
+            Some(VERSION) => {}
+            Some(version) => bail!("unsupported recording version {}", version),
+            None => bail!("recording is truncated"),
+        }
+        let reset = bytes
+            .u64()
+            .ok_or_else(|| anyhow!("recording is truncated"))?;
+        let reset = UNIX_EPOCH + Duration::from_micros(reset);
+
+        let mut records = vec![];
+        while let Some(tag) = bytes.u8() {
+            let record = match tag {
+                CHANNEL => bytes.channel(),
+                DATA => bytes.data(),
+                _ => bail!("recording is corrupted (unknown record type {})", tag),
+            };
+            match record {
+                Some(record) => records.push(record),
+                None => {
+                    // e.g. `probe-run` was killed while writing the last record
+                    log::warn!("the last record of the recording is truncated; ignoring it");
+                    break;
+                }
+            }
+        }
+
+        Ok(Self { reset, records })
+    }
+}
+
+/// Reader over the bytes of a recording
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|bytes| bytes[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn channel(&mut self) -> Option<Record> {
+        let index = self.u32()? as usize;
+        let len = self.u32()? as usize;
+        let name = String::from_utf8_lossy(self.take(len)?).into_owned();
+        Some(Record::Channel {
+            index,
+            name: if name.is_empty() { None } else { Some(name) },
+        })
+    }
+
+    fn data(&mut self) -> Option<Record> {
+        let index = self.u32()? as usize;
+        let elapsed = Duration::from_micros(self.u64()?);
+        let len = self.u32()? as usize;
+        Some(Record::Data {
+            index,
+            elapsed,
+            data: self.take(len)?.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pretty_assertions::assert_eq;
+
+    fn record(reset: SystemTime) -> Vec<u8> {
+        let mut recorder = Recorder::new(vec![], reset).unwrap();
+        recorder.channel(0, Some("defmt")).unwrap();
+        recorder
+            .data(0, Duration::from_micros(1500), &[1, 2, 3])
+            .unwrap();
+        recorder.channel(1, None).unwrap();
+        recorder
+            .data(1, Duration::from_micros(2500), b"hello\n")
+            .unwrap();
+        recorder.writer
+    }
+
+    #[test]
+    fn roundtrip() {
+        let reset = UNIX_EPOCH + Duration::from_micros(1_621_602_245_678_901);
+        let expected = Recording {
+            reset,
+            records: vec![
+                Record::Channel {
+                    index: 0,
+                    name: Some("defmt".to_string()),
+                },
+                Record::Data {
+                    index: 0,
+                    elapsed: Duration::from_micros(1500),
+                    data: vec![1, 2, 3],
+                },
+                Record::Channel {
+                    index: 1,
+                    name: None,
+                },
+                Record::Data {
+                    index: 1,
+                    elapsed: Duration::from_micros(2500),
+                    data: b"hello\n".to_vec(),
+                },
+            ],
+        };
+        assert_eq!(expected, Recording::read(&*record(reset)).unwrap());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = record(UNIX_EPOCH);
+        bytes.truncate(bytes.len() - 2);
+        let recording = Recording::read(&*bytes).unwrap();
+        assert_eq!(3, recording.records.len());
+    }
+
+    #[test]
+    fn not_a_recording() {
+        assert!(Recording::read(&b"\x7fELF"[..]).is_err());
+    }
+}
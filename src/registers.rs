@@ -1,39 +1,62 @@
 use std::collections::{btree_map, BTreeMap};
 
 use gimli::{read::CfaRule, EndianSlice, LittleEndian, Register, RegisterRule};
-use probe_rs::{Core, CoreRegisterAddress, MemoryInterface};
+use probe_rs::{Core, MemoryInterface};
 
-pub const LR: CoreRegisterAddress = CoreRegisterAddress(14);
-pub const PC: CoreRegisterAddress = CoreRegisterAddress(15);
-pub const SP: CoreRegisterAddress = CoreRegisterAddress(13);
+use crate::{arch::Arch, backtrace::UnwindError};
 
-pub const LR_END: u32 = 0xFFFF_FFFF;
+/// Register values of a single stack frame, as recovered during unwinding
+///
+/// Registers are identified by their DWARF register number
+#[derive(Clone, Debug, Default)]
+pub struct FrameRegisters {
+    pub values: BTreeMap<u16, u32>,
+    /// Canonical Frame Address of this frame
+    pub cfa: Option<u32>,
+    /// `true` if no register has been modified by the frame's callees; in that case registers
+    /// missing from `values` can be read from the core
+    pub is_top: bool,
+}
 
 /// Cache and track the state of CPU registers while the stack is being unwound.
+///
+/// Registers are identified by their DWARF register number
 pub struct Registers<'c, 'probe> {
     cache: BTreeMap<u16, u32>,
+    arch: &'c Arch,
     pub core: &'c mut Core<'probe>,
 }
 
 impl<'c, 'probe> Registers<'c, 'probe> {
-    pub fn new(lr: u32, sp: u32, core: &'c mut Core<'probe>) -> Self {
+    pub fn new(lr: u32, sp: u32, arch: &'c Arch, core: &'c mut Core<'probe>) -> Self {
         let mut cache = BTreeMap::new();
-        cache.insert(LR.0, lr);
-        cache.insert(SP.0, sp);
-        Self { cache, core }
+        cache.insert(arch.return_address().0, lr);
+        cache.insert(arch.stack_pointer().0, sp);
+        Self { cache, arch, core }
     }
 
-    pub fn get(&mut self, reg: CoreRegisterAddress) -> anyhow::Result<u32> {
+    pub fn get(&mut self, reg: Register) -> anyhow::Result<u32> {
         Ok(match self.cache.entry(reg.0) {
             btree_map::Entry::Occupied(entry) => *entry.get(),
-            btree_map::Entry::Vacant(entry) => *entry.insert(self.core.read_core_reg(reg)?),
+            btree_map::Entry::Vacant(entry) => {
+                *entry.insert(self.core.read_core_reg(self.arch.dwarf2probe(reg))?)
+            }
         })
     }
 
-    pub fn insert(&mut self, reg: CoreRegisterAddress, val: u32) {
+    pub fn insert(&mut self, reg: Register, val: u32) {
         self.cache.insert(reg.0, val);
     }
 
+    /// Returns the register values known so far
+    pub fn snapshot(&self, is_top: bool) -> FrameRegisters {
+        FrameRegisters {
+            values: self.cache.clone(),
+            cfa: None,
+            is_top,
+        }
+    }
+
     /// Updates the Canonical Frame Address (CFA), e.g.
     /// the value of the Stack Pointer (SP) on function entry – the current frame we're looking at
     ///
@@ -41,20 +64,23 @@ impl<'c, 'probe> Registers<'c, 'probe> {
     pub fn update_cfa(
         &mut self,
         rule: &CfaRule<EndianSlice<LittleEndian>>,
-    ) -> anyhow::Result</* cfa_changed: */ bool> {
+    ) -> Result</* cfa_changed: */ bool, UnwindError> {
         match rule {
             CfaRule::RegisterAndOffset { register, offset } => {
-                let cfa = (i64::from(self.get(gimli2probe(register))?) + offset) as u32;
-                let old_cfa = self.cache.get(&SP.0);
+                let sp = self.arch.stack_pointer();
+                let cfa = (i64::from(self.get(*register)?) + offset) as u32;
+                let old_cfa = self.cache.get(&sp.0);
                 let changed = old_cfa != Some(&cfa);
                 if changed {
                     log::debug!("update_cfa: CFA changed {:8x?} -> {:8x}", old_cfa, cfa);
                 }
-                self.cache.insert(SP.0, cfa);
+                self.cache.insert(sp.0, cfa);
                 Ok(changed)
             }
             // NOTE not encountered in practice so far
-            CfaRule::Expression(_) => todo!("CfaRule::Expression"),
+            CfaRule::Expression(_) => Err(UnwindError::UnsupportedRule(
+                "CFA rule (DWARF expression)".to_string(),
+            )),
         }
     }
 
@@ -62,20 +88,20 @@ impl<'c, 'probe> Registers<'c, 'probe> {
         &mut self,
         reg: &Register,
         rule: &RegisterRule<EndianSlice<LittleEndian>>,
-    ) -> anyhow::Result<()> {
+    ) -> Result<(), UnwindError> {
         match rule {
             RegisterRule::Offset(offset) => {
-                let cfa = self.get(SP)?;
+                let cfa = self.get(self.arch.stack_pointer())?;
                 let addr = (cfa as i64 + offset) as u32;
                 self.cache.insert(reg.0, self.core.read_word_32(addr)?);
             }
-            RegisterRule::Undefined => unreachable!(),
-            _ => unimplemented!(),
+            _ => {
+                return Err(UnwindError::UnsupportedRule(format!(
+                    "rule for register {}: {:?}",
+                    reg.0, rule
+                )))
+            }
         }
         Ok(())
     }
 }
-
-fn gimli2probe(reg: &Register) -> CoreRegisterAddress {
-    CoreRegisterAddress(reg.0)
-}
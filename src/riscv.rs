@@ -0,0 +1,139 @@
+//! RISC-V specific constants
+
+use std::mem;
+
+use anyhow::anyhow;
+use gimli::Register;
+use object::{read::File as ElfFile, Object as _, ObjectSymbol as _};
+use probe_rs::CoreRegisterAddress;
+
+pub(crate) const ADDRESS_SIZE: u8 = mem::size_of::<u32>() as u8;
+
+/// Machine Exception Program Counter
+pub(crate) const MEPC: CoreRegisterAddress = CoreRegisterAddress(0x341);
+/// Machine Cause register
+pub(crate) const MCAUSE: CoreRegisterAddress = CoreRegisterAddress(0x342);
+
+/// DWARF number of the return address register (`x1`)
+pub(crate) const RA: Register = Register(1);
+/// DWARF number of the stack pointer register (`x2`)
+pub(crate) const SP: Register = Register(2);
+/// DWARF number of the first argument register (`x10`)
+pub(crate) const A0: Register = Register(10);
+
+/// Size of the trap frame `riscv-rt` pushes on trap entry: `ra`, `t0`-`t6` and `a0`-`a7`, with
+/// `ra` at its start
+pub(crate) const TRAP_FRAME_SIZE: u32 = 16 * ADDRESS_SIZE as u32;
+
+/// `riscv-rt` jumps into `main` with a zeroed return address register
+pub(crate) const RA_END: u32 = 0;
+
+const INTERRUPT_BIT: u32 = 1 << 31;
+const NUM_GPRS: u16 = 32;
+/// `probe-rs` addresses the general purpose register `xN` as `0x1000 + N`
+const GPR_BASE: u16 = 0x1000;
+
+/// Information about a program that runs on a RISC-V core
+#[derive(Debug)]
+pub(crate) struct Info {
+    /// Initial value of the stack pointer (`_stack_start`)
+    pub(crate) stack_start: u32,
+    /// Address of `riscv-rt`'s `ExceptionHandler`, which only runs on exceptions (not on
+    /// interrupts)
+    pub(crate) exception_handler: Option<u32>,
+}
+
+impl Info {
+    pub(crate) fn from_elf(elf: &ElfFile) -> anyhow::Result<Self> {
+        let stack_start = elf
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("_stack_start"))
+            .map(|symbol| symbol.address() as u32)
+            .ok_or_else(|| anyhow!("`_stack_start` symbol not found"))?;
+
+        let exception_handler = elf
+            .symbols()
+            .find(|symbol| symbol.name() == Ok("ExceptionHandler"))
+            .map(|symbol| symbol.address() as u32);
+        if exception_handler.is_none() {
+            log::warn!("`ExceptionHandler` symbol not found; exceptions will NOT make `probe-run` exit with an error code");
+        }
+
+        Ok(Info {
+            stack_start,
+            exception_handler,
+        })
+    }
+}
+
+/// Maps a DWARF register number to the `probe-rs` register address
+pub(crate) fn dwarf2probe(reg: Register) -> CoreRegisterAddress {
+    debug_assert!(
+        reg.0 < NUM_GPRS,
+        "not a general purpose register: {:?}",
+        reg
+    );
+    CoreRegisterAddress(GPR_BASE + reg.0)
+}
+
+/// Checks if `reg` is one of the general purpose registers `x0`-`x31`
+pub(crate) fn is_gpr(reg: Register) -> bool {
+    reg.0 < NUM_GPRS
+}
+
+/// Checks if the calling convention requires functions to preserve the value of `reg` (`sp` and
+/// `s0`-`s11`)
+pub(crate) fn is_callee_saved(reg: Register) -> bool {
+    matches!(reg.0, 2 | 8 | 9 | 18..=27)
+}
+
+fn is_interrupt(mcause: u32) -> bool {
+    mcause & INTERRUPT_BIT != 0
+}
+
+/// Human readable description of the exception in `mcause`
+pub(crate) fn exception_description(mcause: u32) -> &'static str {
+    if is_interrupt(mcause) {
+        return "interrupt";
+    }
+
+    match mcause {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store/AMO address misaligned",
+        7 => "store/AMO access fault",
+        8 => "environment call from U-mode",
+        9 => "environment call from S-mode",
+        11 => "environment call from M-mode",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store/AMO page fault",
+        _ => "unknown exception",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(2, "illegal instruction")]
+    #[case(7, "store/AMO access fault")]
+    #[case(0x8000_0007, "interrupt")]
+    #[case(42, "unknown exception")]
+    fn exception_descriptions(#[case] mcause: u32, #[case] expected: &str) {
+        assert_eq!(expected, exception_description(mcause));
+    }
+
+    #[test]
+    fn gpr_mapping() {
+        assert_eq!(0x1001, dwarf2probe(RA).0);
+        assert_eq!(0x1002, dwarf2probe(SP).0);
+    }
+}
@@ -0,0 +1,578 @@
+//! Reading the RTT logging channel fast enough to keep up with firmware that logs a lot, and
+//! setting what the firmware does when a channel's buffer is full
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::Range,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use probe_rs::{config::MemoryRegion, Core, MemoryInterface as _};
+use probe_rs_rtt::{ChannelMode, ScanRegion};
+
+/// Size of the first reads
+const MIN_READ_LEN: usize = 1024;
+/// Reads never get larger than this, even if the channel's buffer is larger
+const MAX_READ_LEN: usize = 64 * 1024;
+/// How often to check whether the core halted while data keeps arriving
+const HALT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// ID at the start of the RTT control block, padded with zeros to 16 bytes
+const ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+/// Offset of the number of up channels in the RTT control block
+const MAX_UP_CHANNELS_OFFSET: u32 = 16;
+/// Offset of the first up channel in the RTT control block
+const UP_CHANNELS_OFFSET: u32 = 24;
+/// Size of a channel in the RTT control block
+const CHANNEL_SIZE: u32 = 24;
+/// Offset of the flags in a channel
+const FLAGS_OFFSET: u32 = 20;
+/// The bits of the flags that hold the mode
+const MODE_MASK: u32 = 0b11;
+
+/// What the firmware does when the buffer of an up channel is full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Mode {
+    /// Drop the whole write
+    NoBlockSkip,
+    /// Write as much as fits and drop the rest
+    NoBlockTrim,
+    /// Wait until the host has read enough data
+    BlockIfFull,
+}
+
+impl Mode {
+    fn flags(self) -> u32 {
+        match self {
+            Mode::NoBlockSkip => 0,
+            Mode::NoBlockTrim => 1,
+            Mode::BlockIfFull => 2,
+        }
+    }
+
+    /// Whether the firmware drops data when the buffer is full
+    pub(crate) fn drops_data(self) -> bool {
+        self != Mode::BlockIfFull
+    }
+
+    pub(crate) fn channel_mode(self) -> ChannelMode {
+        match self {
+            Mode::NoBlockSkip => ChannelMode::NoBlockSkip,
+            Mode::NoBlockTrim => ChannelMode::NoBlockTrim,
+            Mode::BlockIfFull => ChannelMode::BlockIfFull,
+        }
+    }
+}
+
+impl From<ChannelMode> for Mode {
+    fn from(mode: ChannelMode) -> Self {
+        match mode {
+            ChannelMode::NoBlockSkip => Mode::NoBlockSkip,
+            ChannelMode::NoBlockTrim => Mode::NoBlockTrim,
+            ChannelMode::BlockIfFull => Mode::BlockIfFull,
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no-block-skip" => Ok(Mode::NoBlockSkip),
+            "no-block-trim" => Ok(Mode::NoBlockTrim),
+            "block-if-full" => Ok(Mode::BlockIfFull),
+            _ => Err(anyhow!(
+                "invalid RTT mode `{}` (expected `no-block-skip`, `no-block-trim` or `block-if-full`)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Mode::NoBlockSkip => "no-block-skip",
+            Mode::NoBlockTrim => "no-block-trim",
+            Mode::BlockIfFull => "block-if-full",
+        })
+    }
+}
+
+/// How `probe-run` waits for the program to initialize RTT before attaching to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Attach {
+    /// Stop the program at a breakpoint on the init symbol (`main` by default)
+    Breakpoint,
+    /// Let the program run and poll the control block until its ID has been written
+    Poll,
+}
+
+impl FromStr for Attach {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "breakpoint" => Ok(Attach::Breakpoint),
+            "poll" => Ok(Attach::Poll),
+            _ => Err(anyhow!(
+                "invalid RTT attach mode `{}` (expected `breakpoint` or `poll`)",
+                s
+            )),
+        }
+    }
+}
+
+/// Modes of the up channels, e.g. `no-block-trim,1=no-block-skip`
+///
+/// A bare mode applies to the channel the logs are read from, `<index>=<mode>` to the up channel
+/// with that index
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Modes {
+    logging_channel: Option<Mode>,
+    channels: Vec<(usize, Mode)>,
+}
+
+impl Modes {
+    /// Returns the mode of each up channel whose mode is set, by index
+    ///
+    /// The logging channel blocks by default so that no logs are lost
+    pub(crate) fn resolve(&self, logging_channel: usize) -> BTreeMap<usize, Mode> {
+        let mut modes = self.explicit(logging_channel);
+        modes.entry(logging_channel).or_insert(Mode::BlockIfFull);
+        modes
+    }
+
+    /// Like `resolve` but only returns the modes that were given
+    pub(crate) fn explicit(&self, logging_channel: usize) -> BTreeMap<usize, Mode> {
+        let mut modes = BTreeMap::new();
+        if let Some(mode) = self.logging_channel {
+            modes.insert(logging_channel, mode);
+        }
+        modes.extend(self.channels.iter().copied());
+        modes
+    }
+}
+
+impl FromStr for Modes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modes = Modes::default();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next().unwrap_or_default(), parts.next()) {
+                (channel, Some(mode)) => {
+                    let channel = channel
+                        .parse()
+                        .map_err(|_| anyhow!("invalid RTT channel index `{}`", channel))?;
+                    modes.channels.push((channel, mode.parse()?));
+                }
+                (mode, None) => modes.logging_channel = Some(mode.parse()?),
+            }
+        }
+        Ok(modes)
+    }
+}
+
+/// Sets the mode of the up channel `channel` in the RTT control block at `control_block`
+///
+/// Returns `false` if the control block doesn't have the channel (yet), in which case the mode
+/// was not set
+pub(crate) fn set_mode(
+    core: &mut Core,
+    control_block: u32,
+    channel: usize,
+    mode: Mode,
+) -> anyhow::Result<bool> {
+    let max_up_channels = core.read_word_32(control_block + MAX_UP_CHANNELS_OFFSET)?;
+    if channel as u32 >= max_up_channels {
+        // e.g. the control block is initialized by the program, after `main` starts
+        log::debug!(
+            "can't set the mode of RTT up channel {}; the control block has {} up channels",
+            channel,
+            max_up_channels
+        );
+        return Ok(false);
+    }
+
+    let address = flags_address(control_block, channel);
+    let flags = core.read_word_32(address)?;
+    core.write_word_32(address, flags & !MODE_MASK | mode.flags())?;
+    log::debug!("set the mode of RTT up channel {} to {}", channel, mode);
+    Ok(true)
+}
+
+fn flags_address(control_block: u32, channel: usize) -> u32 {
+    control_block + UP_CHANNELS_OFFSET + channel as u32 * CHANNEL_SIZE + FLAGS_OFFSET
+}
+
+/// Erases the IDs of the RTT control blocks in `location`
+///
+/// RAM keeps its contents across a reset, so without this, polling would find the control block
+/// left behind by the previous run before the new program has initialized its own. Must be
+/// called while the core is halted after a reset: the program hasn't run yet, and it writes a new
+/// ID when it initializes its control block, so nothing it relies on is lost.
+///
+/// NOTE the ID may be written before the rest of the control block, e.g. `defmt-rtt` copies the
+/// whole block from `.data`, so polling must not stop at the ID alone
+pub(crate) fn clear_ids(core: &mut Core, location: &ScanRegion) -> anyhow::Result<()> {
+    let range = match location {
+        ScanRegion::Exact(address) => *address..*address + ID.len() as u32,
+        ScanRegion::Range(range) => range.clone(),
+        // not used by `probe-run`
+        ScanRegion::Ram => return Ok(()),
+    };
+
+    let mut memory = vec![0; (range.end - range.start) as usize];
+    core.read_8(range.start, &mut memory)?;
+    for offset in find_ids(&memory) {
+        let address = range.start + offset as u32;
+        log::debug!("erasing stale RTT control block ID at {:#010x}", address);
+        core.write_8(address, &[0; ID.len()])?;
+    }
+    Ok(())
+}
+
+/// Checks that the `scan-range` of the configuration is a non-empty range of RAM
+pub(crate) fn scan_range(
+    [start, end]: [u32; 2],
+    memory_map: &[MemoryRegion],
+) -> anyhow::Result<Range<u32>> {
+    if start >= end {
+        bail!(
+            "invalid RTT `scan-range` [0x{:08X}, 0x{:08X}]; the start must be below the end",
+            start,
+            end
+        );
+    }
+
+    let in_ram = memory_map.iter().any(|region| match region {
+        MemoryRegion::Ram(ram) => ram.range.start <= start && end <= ram.range.end,
+        _ => false,
+    });
+    if !in_ram {
+        bail!(
+            "invalid RTT `scan-range` [0x{:08X}, 0x{:08X}]; it must lie within a RAM region of the chip",
+            start,
+            end
+        );
+    }
+
+    Ok(start..end)
+}
+
+/// Returns the offsets of the RTT control block IDs in `memory`
+fn find_ids(memory: &[u8]) -> Vec<usize> {
+    memory
+        .windows(ID.len())
+        .enumerate()
+        .filter(|(_, window)| *window == ID)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+/// Read buffer that grows while the reads fill it, up to the size of the channel's buffer
+pub(crate) struct ReadBuffer {
+    buf: Vec<u8>,
+    max_len: usize,
+}
+
+impl ReadBuffer {
+    /// `channel_len` is the size of the channel's buffer on the device
+    pub(crate) fn new(channel_len: usize) -> Self {
+        Self {
+            buf: vec![0; MIN_READ_LEN],
+            max_len: channel_len.clamp(MIN_READ_LEN, MAX_READ_LEN),
+        }
+    }
+
+    /// Returns a buffer that is large enough to empty the channel's buffer with one read
+    pub(crate) fn whole_channel(channel_len: usize) -> Self {
+        // NOTE not limited to `MAX_READ_LEN`; `Overflows` can't see a full buffer otherwise
+        let max_len = channel_len.max(MIN_READ_LEN);
+        Self {
+            buf: vec![0; max_len],
+            max_len,
+        }
+    }
+
+    pub(crate) fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Returns the `len` bytes that were read into the buffer
+    pub(crate) fn filled(&mut self, len: usize) -> &[u8] {
+        if len == self.buf.len() && self.buf.len() < self.max_len {
+            // the device produces data faster than we read it
+            let new_len = (self.buf.len() * 2).min(self.max_len);
+            log::trace!("increasing RTT read size to {} bytes", new_len);
+            self.buf.resize(new_len, 0);
+        }
+        &self.buf[..len]
+    }
+}
+
+/// Decides when to check whether the core halted
+///
+/// Checking requires locking the session, which slows down reading the channel
+pub(crate) struct HaltPolling {
+    last_check: Option<Instant>,
+}
+
+impl HaltPolling {
+    pub(crate) fn new() -> Self {
+        Self { last_check: None }
+    }
+
+    /// `received_data` is whether the last read returned data
+    pub(crate) fn is_due(&mut self, received_data: bool) -> bool {
+        let now = Instant::now();
+        // NOTE if the core halted, the channel runs dry and the check happens right away
+        let due = !received_data
+            || match self.last_check {
+                Some(last_check) => now - last_check >= HALT_POLL_INTERVAL,
+                None => true,
+            };
+        if due {
+            self.last_check = Some(now);
+        }
+        due
+    }
+}
+
+/// Detects when the channel's buffer was full, in which case the firmware may have dropped data
+///
+/// This is a hint, not a count of lost data: a full buffer may not have lost anything, and in
+/// `no-block-skip` mode writes are also dropped when the buffer is only partly full. On defmt
+/// channels, dropped data shows up as malformed defmt data
+pub(crate) struct Overflows {
+    channel_len: usize,
+    count: u64,
+}
+
+impl Overflows {
+    /// `channel_len` is the size of the channel's buffer on the device
+    pub(crate) fn new(channel_len: usize) -> Self {
+        Self {
+            channel_len,
+            count: 0,
+        }
+    }
+
+    /// Checks whether the buffer was full when `len` bytes were read from it at once
+    pub(crate) fn check(&mut self, len: usize) -> bool {
+        // NOTE the ring buffer holds at most `channel_len - 1` bytes
+        let full = len != 0 && len + 1 >= self.channel_len;
+        if full {
+            self.count += 1;
+        }
+        full
+    }
+
+    /// How often the buffer was found full
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// Throughput of the logging channel
+pub(crate) struct Stats {
+    started: Instant,
+    bytes: u64,
+    reads: u64,
+    largest_read: usize,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes: 0,
+            reads: 0,
+            largest_read: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, len: usize) {
+        self.bytes += len as u64;
+        self.reads += 1;
+        self.largest_read = self.largest_read.max(len);
+    }
+
+    pub(crate) fn report(&self) -> Report {
+        Report {
+            bytes: self.bytes,
+            reads: self.reads,
+            largest_read: self.largest_read,
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
+
+pub(crate) struct Report {
+    bytes: u64,
+    reads: u64,
+    largest_read: usize,
+    elapsed: Duration,
+}
+
+impl fmt::Display for Report {
+    /// e.g. `received 512.00 KiB in 2.00s (256.0 KiB/s) with 300 reads of up to 4096 bytes`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        let kib = self.bytes as f64 / 1024.0;
+        write!(f, "received {:.2} KiB in {:.2}s (", kib, seconds)?;
+        if seconds == 0.0 {
+            f.write_str("- KiB/s")?;
+        } else {
+            write!(f, "{:.1} KiB/s", kib / seconds)?;
+        }
+        write!(
+            f,
+            ") with {} reads of up to {} bytes",
+            self.reads, self.largest_read
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use probe_rs::config::RamRegion;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", &[(0, Mode::BlockIfFull)])]
+    #[case("no-block-trim", &[(0, Mode::NoBlockTrim)])]
+    #[case("1=no-block-skip", &[(0, Mode::BlockIfFull), (1, Mode::NoBlockSkip)])]
+    #[case(
+        "no-block-skip, 0=no-block-trim",
+        &[(0, Mode::NoBlockTrim)]
+    )]
+    fn channel_modes(#[case] modes: &str, #[case] expected: &[(usize, Mode)]) {
+        let modes = modes.parse::<Modes>().unwrap();
+        assert_eq!(
+            expected.iter().copied().collect::<BTreeMap<_, _>>(),
+            modes.resolve(0)
+        );
+    }
+
+    #[rstest]
+    #[case("", &[])]
+    #[case("1=no-block-skip", &[(1, Mode::NoBlockSkip)])]
+    #[case("no-block-trim", &[(0, Mode::NoBlockTrim)])]
+    fn explicit_channel_modes(#[case] modes: &str, #[case] expected: &[(usize, Mode)]) {
+        let modes = modes.parse::<Modes>().unwrap();
+        assert_eq!(
+            expected.iter().copied().collect::<BTreeMap<_, _>>(),
+            modes.explicit(0)
+        );
+    }
+
+    #[rstest]
+    #[case("block")]
+    #[case("x=no-block-skip")]
+    fn invalid_channel_modes(#[case] modes: &str) {
+        assert!(modes.parse::<Modes>().is_err());
+    }
+
+    #[test]
+    fn flags() {
+        // the flags of up channel 0 follow the ID (16 bytes), the numbers of channels and the
+        // name, buffer, size, write and read offset of the channel
+        assert_eq!(0x2000_002C, flags_address(0x2000_0000, 0));
+        assert_eq!(0x2000_0044, flags_address(0x2000_0000, 1));
+    }
+
+    #[test]
+    fn full_buffer() {
+        let mut overflows = Overflows::new(1024);
+        assert!(!overflows.check(0));
+        assert!(!overflows.check(1000));
+        assert!(overflows.check(1023));
+        assert_eq!(1, overflows.count());
+    }
+
+    #[test]
+    fn whole_channel_read_buffer() {
+        assert_eq!(1024, ReadBuffer::whole_channel(16).as_mut().len());
+        assert_eq!(
+            256 * 1024,
+            ReadBuffer::whole_channel(256 * 1024).as_mut().len()
+        );
+    }
+
+    #[test]
+    fn read_buffer_grows() {
+        let mut buffer = ReadBuffer::new(3000);
+        assert_eq!(1024, buffer.as_mut().len());
+
+        buffer.filled(100);
+        assert_eq!(1024, buffer.as_mut().len());
+
+        assert_eq!(1024, buffer.filled(1024).len());
+        assert_eq!(2048, buffer.as_mut().len());
+
+        buffer.filled(2048);
+        assert_eq!(3000, buffer.as_mut().len());
+
+        buffer.filled(3000);
+        assert_eq!(3000, buffer.as_mut().len());
+    }
+
+    #[rstest]
+    #[case([0x2000_0000, 0x2000_1000], Some(0x2000_0000..0x2000_1000))]
+    #[case([0x2000_0000, 0x2001_0000], Some(0x2000_0000..0x2001_0000))]
+    #[case([0x2000_1000, 0x2000_1000], None)]
+    #[case([0x2000_1000, 0x2000_0000], None)]
+    #[case([0x2000_f000, 0x2001_1000], None)]
+    #[case([0x0000_0000, 0x0000_1000], None)]
+    fn scan_ranges(#[case] range: [u32; 2], #[case] expected: Option<Range<u32>>) {
+        let memory_map = [MemoryRegion::Ram(RamRegion {
+            range: 0x2000_0000..0x2001_0000,
+            is_boot_memory: false,
+        })];
+        assert_eq!(expected, scan_range(range, &memory_map).ok());
+    }
+
+    #[test]
+    fn ids() {
+        let mut memory = vec![0xff; 64];
+        memory[4..20].copy_from_slice(ID);
+        memory[40..56].copy_from_slice(ID);
+        assert_eq!(vec![4, 40], find_ids(&memory));
+
+        // not zero padded
+        memory[40 + 10] = b'!';
+        assert_eq!(vec![4], find_ids(&memory));
+        assert!(find_ids(&ID[..8]).is_empty());
+    }
+
+    #[test]
+    fn halt_polling() {
+        let mut polling = HaltPolling::new();
+        assert!(polling.is_due(true));
+        assert!(!polling.is_due(true));
+        assert!(polling.is_due(false));
+    }
+
+    #[test]
+    fn report() {
+        let report = Report {
+            bytes: 512 * 1024,
+            reads: 300,
+            largest_read: 4096,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(
+            "received 512.00 KiB in 2.00s (256.0 KiB/s) with 300 reads of up to 4096 bytes",
+            report.to_string()
+        );
+    }
+}